@@ -16,31 +16,197 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
 //! This module handles loading parts information from database.
+use crate::diag;
 use flate2::read::GzDecoder;
 use roxmltree::{Document, Node};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::error::Error;
+use std::fmt;
 use std::fs::File;
-use std::io::Read;
-use std::path::Path;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
 
-static EXT: &str = ".xml.gz";
+/// Database files extensions, one for each supported compression, and uncompressed.
+static EXTS: &[&str] = &[".xml.gz", ".xml.zst", ".xml.xz", ".xml"];
 
-type Result<T> = std::result::Result<T, Box<dyn Error>>;
+/// File present in CubeMX databases, used to detect an installation root, it does not describe a
+/// part.
+static CUBEMX_MARKER: &str = "families.xml";
+
+/// Name of the index file, in database directory.
+static INDEX: &str = "index.csv";
+
+type Result<T> = std::result::Result<T, DbError>;
+
+/// Error when loading information from database.
+#[derive(Debug)]
+pub enum DbError {
+    /// A database file does not exist.
+    MissingFile(PathBuf),
+    /// The GPIO modes file needed by a part does not exist, with the paths tried.
+    MissingModes { part: String, paths: Vec<PathBuf> },
+    /// A database file or directory can not be read.
+    Io(PathBuf, io::Error),
+    /// A database file can not be decompressed or is not valid UTF-8.
+    Decode(PathBuf, String),
+    /// A database file is not valid XML.
+    Xml(roxmltree::Error),
+    /// An expected XML element is missing.
+    MissingElement(&'static str),
+    /// An expected XML attribute is missing.
+    MissingAttribute { tag: String, attr: String },
+    /// An alternate function can not be parsed.
+    BadAf { signal: String, values: Vec<String> },
+    /// A remap can not be parsed.
+    BadRemap(String),
+    /// The part pattern is not a valid regex.
+    Pattern(regex::Error),
+    /// No part matches the requested name, with parts having a close name.
+    PartNotFound {
+        part: String,
+        suggestions: Vec<String>,
+    },
+    /// Several parts start with the requested name.
+    AmbiguousPart {
+        part: String,
+        candidates: Vec<String>,
+    },
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DbError::MissingFile(path) => write!(f, "can not find {}", path.display()),
+            DbError::MissingModes { part, paths } => write!(
+                f,
+                "part {} requires GPIO modes file {} which was not found in {}",
+                part,
+                paths[0].file_name().unwrap_or_default().to_string_lossy(),
+                paths
+                    .iter()
+                    .map(|path| path.parent().unwrap_or(path).display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            DbError::Io(path, e) => write!(f, "can not read {}: {}", path.display(), e),
+            DbError::Decode(path, e) => write!(f, "can not decode {}: {}", path.display(), e),
+            DbError::Xml(e) => write!(f, "bad XML: {}", e),
+            DbError::MissingElement(tag) => write!(f, "missing {}", tag),
+            DbError::MissingAttribute { tag, attr } => {
+                write!(f, "{} missing a {} attribute", tag, attr)
+            }
+            DbError::BadAf { signal, values } => write!(
+                f,
+                "bad AF for {}, expected GPIO_AF<n>_..., found {:?}",
+                signal, values
+            ),
+            DbError::BadRemap(remap) => write!(f, "bad remap {:?}", remap),
+            DbError::Pattern(e) => write!(f, "bad pattern: {}", e),
+            DbError::PartNotFound { part, suggestions } if suggestions.is_empty() => {
+                write!(f, "part {} not found", part)
+            }
+            DbError::PartNotFound { part, suggestions } => write!(
+                f,
+                "part {} not found, did you mean: {}?",
+                part,
+                suggestions.join(", ")
+            ),
+            DbError::AmbiguousPart { part, candidates } => write!(
+                f,
+                "{} is ambiguous, candidates: {}",
+                part,
+                candidates.join(", ")
+            ),
+        }
+    }
+}
+
+impl Error for DbError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DbError::Io(_, e) => Some(e),
+            DbError::Xml(e) => Some(e),
+            DbError::Pattern(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<roxmltree::Error> for DbError {
+    fn from(e: roxmltree::Error) -> DbError {
+        DbError::Xml(e)
+    }
+}
+
+impl DbError {
+    /// Short identifier for the error class, used in machine-readable diagnostics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DbError::MissingFile(_) => "missing-file",
+            DbError::MissingModes { .. } => "missing-modes",
+            DbError::Io(..) => "io",
+            DbError::Decode(..) => "decode",
+            DbError::Xml(_) => "xml",
+            DbError::MissingElement(_) => "missing-element",
+            DbError::MissingAttribute { .. } => "missing-attribute",
+            DbError::BadAf { .. } => "bad-af",
+            DbError::BadRemap(_) => "bad-remap",
+            DbError::Pattern(_) => "pattern",
+            DbError::PartNotFound { .. } => "part-not-found",
+            DbError::AmbiguousPart { .. } => "ambiguous-part",
+        }
+    }
+    /// Database file the error is about, if known.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            DbError::MissingFile(path) | DbError::Io(path, _) | DbError::Decode(path, _) => {
+                Some(path)
+            }
+            DbError::MissingModes { paths, .. } => paths.first().map(PathBuf::as_path),
+            _ => None,
+        }
+    }
+    /// Make an error from an I/O error on the given path, a missing file gets its own variant.
+    fn io(path: &Path, e: io::Error) -> DbError {
+        if e.kind() == io::ErrorKind::NotFound {
+            DbError::MissingFile(path.to_owned())
+        } else {
+            DbError::Io(path.to_owned(), e)
+        }
+    }
+}
 
 /// Information about a part.
 #[derive(Debug)]
 pub struct PartInfo<'a> {
     /// Part.
     pub part: &'a str,
-    /// Product line.
-    pub line: String,
-    /// Package.
-    pub package: String,
+    /// Product line, if known.
+    pub line: Option<String>,
+    /// Package, if known.
+    pub package: Option<String>,
     /// GPIO mapping mode.
     pub gpio_mode: GpioMode,
+    /// GPIO IP version, if known.
+    pub gpio_version: Option<String>,
     /// Information for all pins.
     pub pins: Vec<PinInfo>,
+    /// Signals, with their pin, handled as additional functions while GPIO modes file has a
+    /// mapping for them on other pins.  This may be a sign of a wrong modes file.
+    pub unmapped_signals: Vec<(String, String)>,
+}
+
+/// Basic information about a part, without its pins.
+#[derive(Debug)]
+pub struct PartHeader<'a> {
+    /// Part.
+    pub part: &'a str,
+    /// Product line, if known.
+    pub line: Option<String>,
+    /// Package, if known.
+    pub package: Option<String>,
+    /// GPIO IP version, if requested and known.
+    pub gpio_version: Option<String>,
 }
 
 /// Information about one pin.
@@ -50,6 +216,8 @@ pub struct PinInfo {
     pub name: String,
     /// Position in package.  This can be a number or a letter with a number.
     pub position: String,
+    /// Pin type, "I/O" for GPIO pins, else "Power", "Reset", "Boot"...
+    pub pin_type: String,
     /// Signals.
     pub signals: Vec<SignalInfo>,
 }
@@ -63,6 +231,17 @@ pub struct SignalInfo {
     pub map: SignalMap,
 }
 
+/// Signal assigned to a pin on a board, to be verified against part information.
+#[derive(Debug)]
+pub struct Assignment {
+    /// Pin name, pin name without its additional function, or position.
+    pub pin: String,
+    /// Signal name.
+    pub signal: String,
+    /// Expected mapping, like `AF7` for an AF, or `1` for a remap, if given.
+    pub mapping: Option<String>,
+}
+
 /// Information on how to map a signal to a pin.
 #[derive(Clone, Debug)]
 pub enum SignalMap {
@@ -71,8 +250,44 @@ pub enum SignalMap {
     /// Additional function, no AF setup to do.
     AddF,
     /// Remap, used on older parts without the AF system.  The signal can be available on several
-    /// remaps.
-    Remap(Vec<u8>),
+    /// remaps, each one is given with the remap group it belongs to, which is the remap block
+    /// name without its number, like `TIM2` for `TIM2_REMAP1`.
+    Remap {
+        remaps: Vec<u8>,
+        groups: Vec<String>,
+    },
+}
+
+impl fmt::Display for SignalMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignalMap::AF(af) => write!(f, "AF{}", af),
+            SignalMap::AddF => write!(f, "ADD"),
+            SignalMap::Remap { remaps, .. } => {
+                let mut remaps = remaps.clone();
+                remaps.sort_unstable();
+                let remaps = remaps.iter().map(u8::to_string).collect::<Vec<_>>();
+                write!(f, "remap {}", remaps.join(","))
+            }
+        }
+    }
+}
+
+impl SignalMap {
+    /// Give the AF number, if this is an alternate function.
+    pub fn af(&self) -> Option<u8> {
+        match self {
+            SignalMap::AF(af) => Some(*af),
+            _ => None,
+        }
+    }
+    /// Give the remaps the signal is available on, if this is a remap.
+    pub fn remaps(&self) -> Option<&[u8]> {
+        match self {
+            SignalMap::Remap { remaps, .. } => Some(remaps),
+            _ => None,
+        }
+    }
 }
 
 /// Mode of GPIO mapping.
@@ -84,134 +299,759 @@ pub enum GpioMode {
     Remap,
 }
 
-/// Map pins and signals to mapping information.  This is used temporarily when loading it from a
-/// separated file.
-type GpiosInfo = HashMap<String, HashMap<String, SignalMap>>;
+/// Names of GPIO mapping modes, as accepted on command line.
+pub static GPIO_MODES: &[&str] = &["af", "remap"];
 
-impl<'a> PartInfo<'a> {
-    /// Extract information from XML file in database.
-    pub fn new(database: &Path, part: &'a str) -> Result<PartInfo<'a>> {
-        // Read XML.
-        let xml_name = database.join(["mcu/", part, EXT].concat());
-        let xml = read_gziped(&xml_name)?;
-        let doc = Document::parse(&xml)?;
+impl std::str::FromStr for GpioMode {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<GpioMode, String> {
+        match s {
+            "af" => Ok(GpioMode::AF),
+            "remap" => Ok(GpioMode::Remap),
+            _ => Err(format!("unknown GPIO mode {}", s)),
+        }
+    }
+}
+
+/// Map pins and signals to mapping information, as loaded from a GPIO modes file.  A signal can
+/// be mapped several times on the same pin, for example using two different AF.
+type GpiosInfo = HashMap<String, HashMap<String, Vec<SignalMap>>>;
+
+/// Database loader, GPIO modes files are parsed once and reused for every part using them.
+#[derive(Debug, Default)]
+pub struct Database {
+    /// Parsed GPIO modes files, indexed by IP directory and GPIO version.
+    gpios: HashMap<(PathBuf, String), (GpioMode, GpiosInfo)>,
+    /// Read index files for each database, `None` if missing or stale.
+    indexes: HashMap<PathBuf, Option<Index>>,
+    /// GPIO mapping mode to use instead of detecting it from GPIO modes files.
+    gpio_mode: Option<GpioMode>,
+    /// Keep the bare GPIO signal of pins.
+    include_gpio: bool,
+}
+
+/// Database index, giving line and package for each part.
+type Index = BTreeMap<String, (Option<String>, Option<String>)>;
+
+impl Database {
+    /// Make a new loader, with an empty cache.
+    pub fn new() -> Database {
+        Database::default()
+    }
+    /// Keep the bare GPIO signal of pins, as an additional function, instead of dropping it.
+    pub fn include_gpio(&mut self) {
+        self.include_gpio = true;
+    }
+    /// Use the given GPIO mapping mode instead of detecting it, GPIO modes files must then follow
+    /// this mode.
+    pub fn force_gpio_mode(&mut self, gpio_mode: GpioMode) {
+        self.gpio_mode = Some(gpio_mode);
+        self.gpios.clear();
+    }
+    /// Extract information from XML file in database.  If `allow_missing_modes` is true, a missing
+    /// GPIO modes file is not an error, all signals are then considered as additional functions.
+    /// GPIO modes files are searched in `mcu/IP`, then in `mcu` for trimmed databases.
+    pub fn part<'a>(
+        &mut self,
+        database: &Path,
+        part: &'a str,
+        allow_missing_modes: bool,
+    ) -> Result<PartInfo<'a>> {
+        let xml_name = database_file(database, &["mcu/", part].concat());
+        let xml = read_xml(&xml_name)?;
+        self.parse_part(
+            &xml,
+            &ip_dirs(&database.join("mcu")),
+            part,
+            allow_missing_modes,
+        )
+    }
+    /// Extract information from the given XML file, GPIO modes files are searched in the first of
+    /// `ip_dirs` containing them, see `ip_dirs`.
+    pub fn part_from_file<'a>(
+        &mut self,
+        xml_name: &Path,
+        ip_dirs: &[PathBuf],
+        part: &'a str,
+        allow_missing_modes: bool,
+    ) -> Result<PartInfo<'a>> {
+        let xml = read_xml(xml_name)?;
+        self.part_from_xml(&xml, ip_dirs, part, allow_missing_modes)
+    }
+    /// Extract information from an already read XML document, GPIO modes files are searched in
+    /// the first of `ip_dirs` containing them, see `ip_dirs`.
+    pub fn part_from_xml<'a>(
+        &mut self,
+        xml: &str,
+        ip_dirs: &[PathBuf],
+        part: &'a str,
+        allow_missing_modes: bool,
+    ) -> Result<PartInfo<'a>> {
+        self.parse_part(xml, ip_dirs, part, allow_missing_modes)
+    }
+    /// Extract information from an XML document, GPIO modes files are searched in the first of
+    /// `ip_dirs` containing them.
+    fn parse_part<'a>(
+        &mut self,
+        xml: &str,
+        ip_dirs: &[PathBuf],
+        part: &'a str,
+        allow_missing_modes: bool,
+    ) -> Result<PartInfo<'a>> {
+        let doc = Document::parse(xml)?;
         let doc_root = doc.root_element();
         // Basic attributes.
-        let line = attribute_or_error(&doc_root, "Line")?;
-        let package = attribute_or_error(&doc_root, "Package")?;
+        let line = optional_attribute(&doc_root, "Line");
+        let package = optional_attribute(&doc_root, "Package");
         // GPIO.
         let gpio_ip = doc_root
             .children()
             .find(|n| n.has_tag_name("IP") && n.attribute("Name") == Some("GPIO"))
-            .ok_or("missing GPIO")?;
+            .ok_or(DbError::MissingElement("GPIO"))?;
         let gpio_version = attribute_or_error(&gpio_ip, "Version")?;
-        let (gpio_mode, gpios_info) = load_gpios(database, &gpio_version)?;
+        let include_gpio = self.include_gpio;
+        let no_gpios;
+        let (gpio_mode, gpios_info) = match self.gpios(ip_dirs, &gpio_version, part) {
+            Err(DbError::MissingModes { .. }) if allow_missing_modes => {
+                diag::warning(
+                    "missing-modes",
+                    &format!(
+                        "{}: missing GPIO modes file for {}, AF are not resolved",
+                        part, gpio_version
+                    ),
+                );
+                no_gpios = (self.gpio_mode.unwrap_or(GpioMode::AF), GpiosInfo::new());
+                &no_gpios
+            }
+            r => r?,
+        };
         // Pins.
         fn parse_signal(
-            signals_map: Option<&HashMap<String, SignalMap>>,
+            signals_map: Option<&HashMap<String, Vec<SignalMap>>>,
             s: Node,
-        ) -> Result<SignalInfo> {
+        ) -> Result<Vec<SignalInfo>> {
             let name = attribute_or_error(&s, "Name")?;
-            let map = match signals_map {
-                None => SignalMap::AddF,
-                Some(signals_map) => signals_map.get(&name).unwrap_or(&SignalMap::AddF).clone(),
+            let maps = signals_map.and_then(|signals_map| signals_map.get(&name));
+            let signals = match maps {
+                None => vec![SignalInfo::new(name, SignalMap::AddF)],
+                Some(maps) => maps
+                    .iter()
+                    .map(|map| SignalInfo::new(name.clone(), map.clone()))
+                    .collect(),
             };
-            Ok(SignalInfo { name, map })
+            Ok(signals)
         }
-        fn parse_pin(gpios_info: &GpiosInfo, n: Node) -> Result<PinInfo> {
+        fn parse_pin(gpios_info: &GpiosInfo, include_gpio: bool, n: Node) -> Result<PinInfo> {
             let name = attribute_or_error(&n, "Name")?;
             let position = attribute_or_error(&n, "Position")?;
+            let pin_type = attribute_or_error(&n, "Type")?;
             let signals = n
                 .children()
-                .filter(|s| s.has_tag_name("Signal") && s.attribute("Name") != Some("GPIO"))
+                .filter(|s| {
+                    s.has_tag_name("Signal")
+                        && (include_gpio || s.attribute("Name") != Some("GPIO"))
+                })
                 .map(|s| {
                     let signals_map = gpios_info.get(&name);
                     parse_signal(signals_map, s)
                 })
-                .collect::<Result<_>>()?;
-            Ok(PinInfo {
-                name,
-                position,
-                signals,
-            })
+                .collect::<Result<Vec<_>>>()?;
+            let signals = signals.into_iter().flatten().collect();
+            Ok(PinInfo::new(name, position, pin_type, signals))
         }
         let pins = doc_root
             .children()
             .filter(|n| n.has_tag_name("Pin"))
-            .map(|n| parse_pin(&gpios_info, n))
-            .collect::<Result<_>>()?;
+            .map(|n| parse_pin(gpios_info, include_gpio, n))
+            .collect::<Result<Vec<_>>>()?;
+        // Signals known in modes file but not for their pin.
+        let known = gpios_info
+            .values()
+            .flat_map(|signals| signals.keys())
+            .map(String::as_str)
+            .collect::<BTreeSet<_>>();
+        let unmapped_signals = pins
+            .iter()
+            .flat_map(|pin| pin.signals.iter().map(move |signal| (pin, signal)))
+            .filter(|(_, signal)| {
+                matches!(signal.map, SignalMap::AddF) && known.contains(signal.name.as_str())
+            })
+            .map(|(pin, signal)| (pin.name.clone(), signal.name.clone()))
+            .collect();
         // Done.
-        Ok(PartInfo {
+        let mut part_info = PartInfo::from_pins(part, line, package, *gpio_mode, pins);
+        part_info.gpio_version = Some(gpio_version);
+        part_info.unmapped_signals = unmapped_signals;
+        Ok(part_info)
+    }
+    /// Read only basic part information, using the database index if available, see
+    /// `PartInfo::header`.  The index does not contain the GPIO version, it is not used when
+    /// `with_gpio` is true.
+    pub fn header<'a>(
+        &mut self,
+        database: &Path,
+        part: &'a str,
+        with_gpio: bool,
+    ) -> Result<PartHeader<'a>> {
+        let index = self
+            .indexes
+            .entry(database.to_owned())
+            .or_insert_with(|| read_index(database));
+        index_header(index, database, part, with_gpio)
+    }
+    /// Read basic information for several parts, see `Database::header`.  Parts are read in
+    /// parallel when the `parallel` feature is enabled, results are in the same order as parts.
+    pub fn headers<'a>(
+        &mut self,
+        parts: &'a [(&Path, String)],
+        with_gpio: bool,
+    ) -> Vec<Result<PartHeader<'a>>> {
+        for (database, _) in parts {
+            if !self.indexes.contains_key(*database) {
+                self.indexes
+                    .insert(database.to_path_buf(), read_index(database));
+            }
+        }
+        let indexes = &self.indexes;
+        let header = |(database, part): &'a (&Path, String)| {
+            index_header(&indexes[*database], database, part, with_gpio)
+        };
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+            parts.par_iter().map(header).collect()
+        }
+        #[cfg(not(feature = "parallel"))]
+        {
+            parts.iter().map(header).collect()
+        }
+    }
+    /// Get information on GPIOs, loading it from the first directory containing the GPIO modes
+    /// file if not already done.
+    fn gpios(
+        &mut self,
+        ip_dirs: &[PathBuf],
+        gpio_version: &str,
+        part: &str,
+    ) -> Result<&(GpioMode, GpiosInfo)> {
+        let name = ["GPIO-", gpio_version, "_Modes"].concat();
+        let ip_dir = ip_dirs
+            .iter()
+            .find(|ip_dir| database_file(ip_dir, &name).is_file())
+            .ok_or_else(|| DbError::MissingModes {
+                part: part.to_owned(),
+                paths: ip_dirs
+                    .iter()
+                    .map(|ip_dir| database_file(ip_dir, &name))
+                    .collect(),
+            })?;
+        let key = (ip_dir.to_owned(), gpio_version.to_owned());
+        if !self.gpios.contains_key(&key) {
+            let gpios = load_gpios(ip_dir, gpio_version, self.gpio_mode)?;
+            self.gpios.insert(key.clone(), gpios);
+        }
+        Ok(&self.gpios[&key])
+    }
+}
+
+impl<'a> PartInfo<'a> {
+    /// Extract information from XML file in database, see `Database::part`.  Use a `Database`
+    /// when loading several parts.
+    pub fn new(database: &Path, part: &'a str, allow_missing_modes: bool) -> Result<PartInfo<'a>> {
+        Database::new().part(database, part, allow_missing_modes)
+    }
+    /// Extract information from the given XML file, see `Database::part_from_file`.
+    pub fn from_file(
+        xml_name: &Path,
+        ip_dirs: &[PathBuf],
+        part: &'a str,
+        allow_missing_modes: bool,
+    ) -> Result<PartInfo<'a>> {
+        Database::new().part_from_file(xml_name, ip_dirs, part, allow_missing_modes)
+    }
+    /// Extract information from an already read XML document, see `Database::part_from_xml`.
+    pub fn from_xml(
+        xml: &str,
+        ip_dirs: &[PathBuf],
+        part: &'a str,
+        allow_missing_modes: bool,
+    ) -> Result<PartInfo<'a>> {
+        Database::new().part_from_xml(xml, ip_dirs, part, allow_missing_modes)
+    }
+    /// Read only basic part information from XML file in database.  This is much faster than
+    /// loading the whole part as pins and GPIO modes are not parsed.  The GPIO version is only
+    /// read if `with_gpio` is true, as this needs the whole file.
+    pub fn header(database: &Path, part: &'a str, with_gpio: bool) -> Result<PartHeader<'a>> {
+        let xml_name = database_file(database, &["mcu/", part].concat());
+        let xml = if with_gpio {
+            read_xml(&xml_name)?
+        } else {
+            read_xml_head(&xml_name)?
+        };
+        let doc = Document::parse(&xml)?;
+        let doc_root = doc.root_element();
+        let gpio_version = doc_root
+            .children()
+            .find(|n| n.has_tag_name("IP") && n.attribute("Name") == Some("GPIO"))
+            .and_then(|n| optional_attribute(&n, "Version"));
+        Ok(PartHeader {
+            part,
+            line: optional_attribute(&doc_root, "Line"),
+            package: optional_attribute(&doc_root, "Package"),
+            gpio_version,
+        })
+    }
+    /// Make part information from already known pins, this is useful to make synthetic parts.
+    pub fn from_pins(
+        part: &'a str,
+        line: Option<String>,
+        package: Option<String>,
+        gpio_mode: GpioMode,
+        pins: Vec<PinInfo>,
+    ) -> PartInfo<'a> {
+        PartInfo {
             part,
             line,
             package,
             gpio_mode,
+            gpio_version: None,
             pins,
+            unmapped_signals: Vec::new(),
+        }
+    }
+    /// List GPIO ports present on this part, sorted.
+    pub fn ports(&self) -> Vec<char> {
+        let ports: BTreeSet<_> = self
+            .pins
+            .iter()
+            .filter_map(|pin| pin.gpio().map(|(port, _)| port))
+            .collect();
+        ports.into_iter().collect()
+    }
+    /// Find a pin by name, name without its additional function, or position.
+    pub fn find_pin(&self, pin: &str) -> Option<&PinInfo> {
+        self.pins
+            .iter()
+            .find(|p| p.name == pin || p.split_name().0 == pin || p.position == pin)
+    }
+    /// Iterate over all signals of the part, with the pin they are available on.  A signal
+    /// available on several pins is given once for each pin.
+    pub fn iter_signals(&self) -> impl Iterator<Item = (&PinInfo, &SignalInfo)> {
+        self.pins
+            .iter()
+            .flat_map(|pin| pin.signals.iter().map(move |signal| (pin, signal)))
+    }
+    /// Only keep signals which can be placed on a single pin.
+    pub fn retain_unique_signals(&mut self) {
+        let mut count = HashMap::new();
+        for pin in &self.pins {
+            // A signal can be mapped several times on the same pin.
+            let signals: BTreeSet<_> = pin.signals.iter().map(|signal| &signal.name).collect();
+            for signal in signals {
+                *count.entry(signal.clone()).or_insert(0) += 1;
+            }
+        }
+        for pin in &mut self.pins {
+            pin.signals.retain(|signal| count[&signal.name] == 1);
+        }
+    }
+    /// Guess part family from its line, for example `F4` for `STM32F405/415`, or `WB` for
+    /// `STM32WB55`.
+    pub fn family(&self) -> Option<String> {
+        let line = self.line.as_ref()?.strip_prefix("STM32")?;
+        let letters = line.find(|c: char| !c.is_ascii_alphabetic())?;
+        match letters {
+            0 => None,
+            1 => Some(line[..2].to_owned()).filter(|f| f.as_bytes()[1].is_ascii_digit()),
+            _ => Some(line[..letters].to_owned()),
+        }
+    }
+    /// Produce a one-line part summary, including the GPIO version if known.
+    pub fn summary(&self) -> String {
+        summary(self.part, &self.line, &self.package, &self.gpio_version)
+    }
+    /// Check part consistency, return a description of each problem found: several pins at the
+    /// same position, several I/O pins with the same name, or unmapped signals.  Other pins, like
+    /// power supply pins, usually appear several times.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut positions: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        let mut names: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for pin in &self.pins {
+            positions.entry(&pin.position).or_default().push(&pin.name);
+            if pin.pin_type == "I/O" {
+                names.entry(&pin.name).or_default().push(&pin.position);
+            }
+        }
+        for (position, names) in positions.iter().filter(|(_, v)| v.len() > 1) {
+            problems.push(format!(
+                "{}: several pins at position {}: {}",
+                self.part,
+                position,
+                names.join(", ")
+            ));
+        }
+        for (name, positions) in names.iter().filter(|(_, v)| v.len() > 1) {
+            problems.push(format!(
+                "{}: pin {} at several positions: {}",
+                self.part,
+                name,
+                positions.join(", ")
+            ));
+        }
+        for (pin, signal) in &self.unmapped_signals {
+            problems.push(format!(
+                "{}: signal {} on pin {} has no mapping in GPIO modes file",
+                self.part, signal, pin
+            ));
+        }
+        problems
+    }
+    /// Check board assignments, return a description of each illegal one: unknown pin, signal
+    /// not available on its pin, or not with the expected mapping.
+    pub fn verify(&self, assignments: &[Assignment]) -> Vec<String> {
+        let mut problems = Vec::new();
+        for assignment in assignments {
+            let (pin, signal) = (&assignment.pin, &assignment.signal);
+            let pin_info = match self.find_pin(pin) {
+                Some(pin_info) => pin_info,
+                None => {
+                    problems.push(format!("{}: no pin {}", self.part, pin));
+                    continue;
+                }
+            };
+            let maps = pin_info
+                .signals
+                .iter()
+                .filter(|s| &s.name == signal)
+                .map(|s| &s.map)
+                .collect::<Vec<_>>();
+            if maps.is_empty() {
+                problems.push(format!(
+                    "{}: signal {} is not available on pin {}",
+                    self.part, signal, pin
+                ));
+                continue;
+            }
+            if let Some(mapping) = &assignment.mapping {
+                let matching = |map: &&SignalMap| match map {
+                    SignalMap::AF(af) => mapping.strip_prefix("AF") == Some(&af.to_string()),
+                    SignalMap::AddF => mapping == "ADD",
+                    SignalMap::Remap { remaps, .. } => {
+                        remaps.iter().any(|remap| *mapping == remap.to_string())
+                    }
+                };
+                if !maps.iter().any(matching) {
+                    let found = maps.iter().map(|map| map.to_string()).collect::<Vec<_>>();
+                    problems.push(format!(
+                        "{}: signal {} on pin {} is not available with {}, but with {}",
+                        self.part,
+                        signal,
+                        pin,
+                        mapping,
+                        found.join(", ")
+                    ));
+                }
+            }
+        }
+        problems
+    }
+}
+
+impl<'a> PartHeader<'a> {
+    /// Produce a one-line part summary, same as `PartInfo::summary`.
+    pub fn summary(&self) -> String {
+        summary(self.part, &self.line, &self.package, &self.gpio_version)
+    }
+}
+
+/// Read basic part information from the database index if possible, else from the part file.
+fn index_header<'a>(
+    index: &Option<Index>,
+    database: &Path,
+    part: &'a str,
+    with_gpio: bool,
+) -> Result<PartHeader<'a>> {
+    let index = index.as_ref().filter(|_| !with_gpio);
+    match index.and_then(|index| index.get(part)) {
+        Some((line, package)) => Ok(PartHeader {
+            part,
+            line: line.clone(),
+            package: package.clone(),
+            gpio_version: None,
+        }),
+        None => PartInfo::header(database, part, with_gpio),
+    }
+}
+
+/// Produce a one-line part summary, missing line or package is reported as unknown, missing GPIO
+/// version is left out.
+fn summary(
+    part: &str,
+    line: &Option<String>,
+    package: &Option<String>,
+    gpio_version: &Option<String>,
+) -> String {
+    let line = line.as_deref().unwrap_or("unknown");
+    let package = package.as_deref().unwrap_or("unknown");
+    match gpio_version {
+        Some(gpio_version) => format!("{}: {} {} (GPIO {})", part, line, package, gpio_version),
+        None => format!("{}: {} {}", part, line, package),
+    }
+}
+
+impl PinInfo {
+    /// Make pin information.
+    pub fn new(
+        name: impl Into<String>,
+        position: impl Into<String>,
+        pin_type: impl Into<String>,
+        signals: Vec<SignalInfo>,
+    ) -> PinInfo {
+        PinInfo {
+            name: name.into(),
+            position: position.into(),
+            pin_type: pin_type.into(),
+            signals,
+        }
+    }
+    /// Tell whether this pin hosts SWD, JTAG or trace signals.
+    pub fn has_debug(&self) -> bool {
+        self.signals.iter().any(|signal| {
+            let name = signal.name.as_str();
+            ["SYS_JT", "SYS_SW", "SYS_TRACE", "DEBUG_"]
+                .iter()
+                .any(|prefix| name.starts_with(prefix))
         })
     }
-    /// Produce a one-line part summary.
-    pub fn summary(self: &Self) -> String {
-        format!("{}: {} {}", self.part, self.line, self.package)
+    /// Split pin name into its base name and its descriptive suffix, if any.  For example
+    /// `PC14-OSC32_IN` gives `PC14` and `OSC32_IN`.
+    pub fn split_name(&self) -> (&str, Option<&str>) {
+        match self.name.find(|c: char| c == '-' || c.is_whitespace()) {
+            Some(i) => {
+                let suffix = self.name[i + 1..].trim();
+                (&self.name[..i], Some(suffix).filter(|s| !s.is_empty()))
+            }
+            None => (&self.name, None),
+        }
+    }
+    /// Parse GPIO port letter and pin number from pin name, return `None` if the name does not
+    /// follow the `P<port><number>` pattern.  Descriptive suffix is ignored.
+    pub fn gpio(&self) -> Option<(char, u8)> {
+        let mut chars = self.split_name().0.chars();
+        if chars.next() != Some('P') {
+            return None;
+        }
+        let port = chars.next().filter(char::is_ascii_uppercase)?;
+        let number = chars.as_str();
+        if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        Some((port, number.parse().ok()?))
+    }
+}
+
+impl SignalInfo {
+    /// Make signal information.
+    pub fn new(name: impl Into<String>, map: SignalMap) -> SignalInfo {
+        SignalInfo {
+            name: name.into(),
+            map,
+        }
+    }
+}
+
+/// Find the database directory from a path given by the user, which can be a database or a CubeMX
+/// installation root, in which case its `db` directory is returned.
+pub fn database_root(path: &Path) -> PathBuf {
+    let cubemx_db = path.join("db");
+    if cubemx_db.join("mcu").join(CUBEMX_MARKER).is_file() {
+        cubemx_db
+    } else {
+        path.to_owned()
     }
 }
 
-/// List all parts in database matching a given regex.
-pub fn list_parts(database: &Path, pattern: &str) -> Result<Vec<String>> {
-    let re = regex::Regex::new(pattern)?;
+/// List all parts in databases matching a given regex.  Each part is returned with the database it
+/// was found in, a part present in several databases is listed once for each of them.  The
+/// database index is used if available, else the database directory is scanned.
+pub fn list_parts<'d>(databases: &'d [PathBuf], pattern: &str) -> Result<Vec<(&'d Path, String)>> {
+    let re = regex::Regex::new(pattern).map_err(DbError::Pattern)?;
     let mut list = Vec::new();
-    for entry in database.join("mcu").read_dir()? {
-        if let Some(name) = entry?.file_name().to_str() {
-            if name.ends_with(EXT) {
-                let part = &name[..(name.len() - EXT.len())];
-                if re.is_match(part) {
-                    list.push(part.to_owned());
+    for (i, database) in databases.iter().enumerate() {
+        // Do not scan the same database twice.
+        if databases[..i].contains(database) {
+            continue;
+        }
+        let parts = match read_index(database) {
+            Some(index) => index.into_keys().collect(),
+            None => scan_parts(database)?,
+        };
+        let parts = parts.into_iter().filter(|part| re.is_match(part));
+        list.extend(parts.map(|part| (database.as_path(), part)));
+    }
+    Ok(list)
+}
+
+/// List all parts in database directory.
+fn scan_parts(database: &Path) -> Result<Vec<String>> {
+    let mut parts: Vec<String> = Vec::new();
+    let dir = database.join("mcu");
+    for entry in dir.read_dir().map_err(|e| DbError::io(&dir, e))? {
+        let entry = entry.map_err(|e| DbError::io(&dir, e))?;
+        if let Some(name) = entry
+            .file_name()
+            .to_str()
+            // GPIO modes files can be stored next to parts in trimmed databases.
+            .filter(|name| *name != CUBEMX_MARKER && !name.starts_with("GPIO-"))
+        {
+            if let Some(part) = EXTS.iter().find_map(|ext| name.strip_suffix(ext)) {
+                // A part can be present with several compressions.
+                if !parts.iter().any(|p| p == part) {
+                    parts.push(part.to_owned());
                 }
             }
         }
     }
-    Ok(list)
+    Ok(parts)
+}
+
+/// Read database index, return `None` if there is no index, if it can not be read, or if it is
+/// older than the database directory or any file in it, meaning that parts were added, removed or
+/// modified since its creation.
+fn read_index(database: &Path) -> Option<Index> {
+    let path = database.join(INDEX);
+    let index_time = path.metadata().and_then(|m| m.modified()).ok()?;
+    let dir = database.join("mcu");
+    let mut newest = dir.metadata().and_then(|m| m.modified()).ok()?;
+    for entry in dir.read_dir().ok()? {
+        let time = entry.and_then(|e| e.metadata()).and_then(|m| m.modified());
+        newest = newest.max(time.ok()?);
+    }
+    if newest > index_time {
+        return None;
+    }
+    let mut reader = csv::Reader::from_path(&path).ok()?;
+    let mut index = Index::new();
+    for record in reader.records() {
+        let record = record.ok()?;
+        let field = |i| record.get(i).filter(|f| !f.is_empty()).map(str::to_owned);
+        index.insert(record.get(0)?.to_owned(), (field(1), field(2)));
+    }
+    Some(index)
+}
+
+/// Write database index, listing all parts with their line and package, return the number of
+/// indexed parts.  Parts which can not be read are reported and indexed with empty fields, so
+/// that they are still listed.
+pub fn write_index(database: &Path) -> Result<usize> {
+    let path = database.join(INDEX);
+    let mut parts = scan_parts(database)?;
+    parts.sort();
+    let mut writer = csv::Writer::from_path(&path).map_err(|e| DbError::io(&path, e.into()))?;
+    let write_error = |e: csv::Error| DbError::io(&path, e.into());
+    writer
+        .write_record(["Part", "Line", "Package"])
+        .map_err(write_error)?;
+    for part in &parts {
+        let (line, package) = match PartInfo::header(database, part, false) {
+            Ok(header) => (header.line, header.package),
+            Err(e) => {
+                diag::warning("bad-part", &format!("{}: {}", part, e));
+                (None, None)
+            }
+        };
+        writer
+            .write_record([
+                part,
+                line.as_deref().unwrap_or(""),
+                package.as_deref().unwrap_or(""),
+            ])
+            .map_err(write_error)?;
+    }
+    writer.flush().map_err(|e| DbError::io(&path, e))?;
+    Ok(parts.len())
+}
+
+/// Directories where GPIO modes files are searched for parts stored in the `mcu` directory:
+/// `mcu/IP`, then `mcu` for trimmed databases.
+pub fn ip_dirs(mcu: &Path) -> Vec<PathBuf> {
+    vec![mcu.join("IP"), mcu.to_owned()]
+}
+
+/// Find the first database containing the given part.
+pub fn find_database<'d>(databases: &'d [PathBuf], part: &str) -> Option<&'d Path> {
+    databases
+        .iter()
+        .find(|database| database_file(database, &["mcu/", part].concat()).is_file())
+        .map(PathBuf::as_path)
 }
 
 /// Load information on GPIOs from XML file in database.  Return a hash indexed by pin and signal,
-/// giving signal mapping information.
-fn load_gpios(database: &Path, gpio_version: &str) -> Result<(GpioMode, GpiosInfo)> {
+/// giving signal mapping information.  Mode is detected from the first signal unless given.
+fn load_gpios(
+    ip_dir: &Path,
+    gpio_version: &str,
+    gpio_mode: Option<GpioMode>,
+) -> Result<(GpioMode, GpiosInfo)> {
     // Read XML.
-    let xml_name = database.join(["mcu/IP/GPIO-", gpio_version, "_Modes", EXT].concat());
-    let xml = read_gziped(&xml_name)?;
+    let xml_name = database_file(ip_dir, &["GPIO-", gpio_version, "_Modes"].concat());
+    let xml = read_xml(&xml_name)?;
     let doc = Document::parse(&xml)?;
     let doc_root = doc.root_element();
     // Decode document.
     fn parse_af(signal: Node) -> Result<SignalMap> {
-        let af = signal
+        // Several values can be listed, use the first one looking like an AF.
+        fn af_num(af: &str) -> Option<u8> {
+            let num = af.strip_prefix("GPIO_AF")?;
+            let i = num.find('_')?;
+            num[..i].parse::<u8>().ok()
+        }
+        let afs = signal
             .descendants()
-            .find(|n| n.has_tag_name("PossibleValue"))
-            .ok_or("no AF found")?
-            .text()
-            .ok_or("no AF text")?;
-        let k = "GPIO_AF";
-        if !af.starts_with(k) {
-            return Err("not an AF".into());
-        }
-        let i = af[k.len()..].find('_').ok_or("not an AF")?;
-        let af = &af[k.len()..k.len() + i];
-        let af = af.parse::<u8>()?;
-        Ok(SignalMap::AF(af))
+            .filter(|n| n.has_tag_name("PossibleValue"))
+            .map(|n| n.text().unwrap_or("").trim())
+            .collect::<Vec<_>>();
+        if afs.is_empty() {
+            return Err(DbError::MissingElement("PossibleValue"));
+        }
+        match afs.iter().find_map(|af| af_num(af)) {
+            Some(num) => Ok(SignalMap::AF(num)),
+            None => Err(DbError::BadAf {
+                signal: attribute_or_error(&signal, "Name")?,
+                values: afs.into_iter().map(String::from).collect(),
+            }),
+        }
     }
     fn parse_remaps(signal: Node) -> Result<SignalMap> {
         let remap_blocks = signal.children().filter(|n| n.has_tag_name("RemapBlock"));
-        fn parse_remap(n: Node) -> Result<u8> {
+        // Names are like `TIM2_REMAP1`, the group is the part before `REMAP`, the number can be
+        // separated by an underscore.
+        fn parse_remap(n: Node) -> Result<(u8, String)> {
             let name = attribute_or_error(&n, "Name")?;
             let k = "REMAP";
-            let i = name.rfind(k).ok_or("missing REMAP")?;
-            let remap = name[i + k.len()..].parse::<u8>()?;
-            Ok(remap)
+            let bad_remap = || DbError::BadRemap(name.clone());
+            let i = name.rfind(k).ok_or_else(bad_remap)?;
+            let num = name[i + k.len()..].trim_start_matches('_');
+            let remap = num.parse::<u8>().map_err(|_| bad_remap())?;
+            let group = name[..i].trim_end_matches('_');
+            let group = if group.is_empty() {
+                &name[..i + k.len()]
+            } else {
+                group
+            };
+            Ok((remap, group.to_owned()))
         }
-        let remaps = remap_blocks.map(parse_remap).collect::<Result<_>>()?;
-        Ok(SignalMap::Remap(remaps))
+        let (remaps, groups) = remap_blocks
+            .map(parse_remap)
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .unzip();
+        Ok(SignalMap::Remap { remaps, groups })
     }
     let mut gpios = HashMap::new();
     let pins = doc_root.children().filter(|n| n.has_tag_name("GPIO_Pin"));
-    let mut mode = None;
+    let mut mode = gpio_mode;
     for pin in pins {
         let pin_name = attribute_or_error(&pin, "Name")?;
         let signals = pin.children().filter(|n| n.has_tag_name("PinSignal"));
@@ -231,28 +1071,140 @@ fn load_gpios(database: &Path, gpio_version: &str) -> Result<(GpioMode, GpiosInf
                 GpioMode::Remap => parse_remaps(signal),
             }?;
             let signal_name = attribute_or_error(&signal, "Name")?;
-            signals_map.insert(signal_name, map);
+            signals_map
+                .entry(signal_name)
+                .or_insert_with(Vec::new)
+                .push(map);
         }
         gpios.insert(pin_name, signals_map);
     }
     Ok((mode.unwrap_or(GpioMode::AF), gpios))
 }
 
-/// Read gziped file to string.
-fn read_gziped(path: &Path) -> Result<String> {
-    let mut gunzip = GzDecoder::new(File::open(path)?);
-    let mut xml = String::new();
-    gunzip.read_to_string(&mut xml)?;
-    Ok(xml)
+/// Find a database file from its name without extension, relative to the given directory, trying
+/// every supported compression and uncompressed.  If none is found, return the name with the first
+/// extension so that the reported error is meaningful.
+fn database_file(dir: &Path, name: &str) -> PathBuf {
+    EXTS.iter()
+        .map(|ext| dir.join([name, ext].concat()))
+        .find(|path| path.is_file())
+        .unwrap_or_else(|| dir.join([name, EXTS[0]].concat()))
+}
+
+/// Read XML file to string, see `decoder` for supported compressions.
+fn read_xml(path: &Path) -> Result<String> {
+    diag::debug(&format!("reading {}", path.display()));
+    let data = read_file(path)?;
+    let mut xml = Vec::new();
+    decoder(path, &data)?
+        .read_to_end(&mut xml)
+        .map_err(|e| DbError::Decode(path.to_owned(), e.to_string()))?;
+    String::from_utf8(xml).map_err(|e| DbError::Decode(path.to_owned(), e.to_string()))
+}
+
+/// Read the beginning of XML file, up to the root element start tag, and return it as a document
+/// containing only the root element, without children.  If the start tag is not found, the whole
+/// file is returned so that the parser can report the problem.
+fn read_xml_head(path: &Path) -> Result<String> {
+    let data = read_file(path)?;
+    let mut decoder = decoder(path, &data)?;
+    let mut xml = Vec::new();
+    let mut chunk = [0; 4096];
+    let end = loop {
+        let n = decoder
+            .read(&mut chunk)
+            .map_err(|e| DbError::Decode(path.to_owned(), e.to_string()))?;
+        if n == 0 {
+            break None;
+        }
+        xml.extend_from_slice(&chunk[..n]);
+        if let Some(end) = root_start_tag_end(&xml) {
+            break Some(end);
+        }
+    };
+    if let Some(end) = end {
+        xml.truncate(end);
+        if !xml.ends_with(b"/>") {
+            xml.pop();
+            xml.extend_from_slice(b"/>");
+        }
+    }
+    String::from_utf8(xml).map_err(|e| DbError::Decode(path.to_owned(), e.to_string()))
+}
+
+/// Find the end of the root element start tag, skipping XML declaration, comments and doctype.
+/// Return the index just after the closing `>`, or `None` if not complete yet.
+fn root_start_tag_end(xml: &[u8]) -> Option<usize> {
+    fn find(data: &[u8], pattern: &[u8]) -> Option<usize> {
+        data.windows(pattern.len()).position(|w| w == pattern)
+    }
+    let mut i = 0;
+    loop {
+        let start = i + find(&xml[i..], b"<")?;
+        let tag = &xml[start..];
+        i = if tag.starts_with(b"<?") {
+            start + find(tag, b"?>")? + 2
+        } else if tag.starts_with(b"<!--") {
+            start + find(tag, b"-->")? + 3
+        } else if tag.starts_with(b"<!") {
+            start + find(tag, b">")? + 1
+        } else {
+            // Attribute values can contain a '>'.
+            let mut quote = None;
+            for (j, &b) in tag.iter().enumerate() {
+                match quote {
+                    Some(q) if b == q => quote = None,
+                    Some(_) => (),
+                    None if b == b'"' || b == b'\'' => quote = Some(b),
+                    None if b == b'>' => return Some(start + j + 1),
+                    None => (),
+                }
+            }
+            return None;
+        };
+    }
+}
+
+/// Read a whole file.
+fn read_file(path: &Path) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut data))
+        .map_err(|e| DbError::io(path, e))?;
+    Ok(data)
+}
+
+/// Make a decoder for a file content, compression is detected using the file magic bytes, a file
+/// without a known magic is read uncompressed.
+fn decoder<'d>(path: &Path, data: &'d [u8]) -> Result<Box<dyn Read + 'd>> {
+    let decode = |e: &dyn fmt::Display| DbError::Decode(path.to_owned(), e.to_string());
+    let decoder: Box<dyn Read> = if data.starts_with(&[0x1f, 0x8b]) {
+        Box::new(GzDecoder::new(data))
+    } else if data.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Box::new(ruzstd::StreamingDecoder::new(data).map_err(|e| decode(&e))?)
+    } else if data.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        // No streaming decoder, decode it at once.
+        let mut decoded = Vec::new();
+        lzma_rs::xz_decompress(&mut &data[..], &mut decoded).map_err(|e| decode(&e))?;
+        Box::new(io::Cursor::new(decoded))
+    } else {
+        Box::new(data)
+    };
+    Ok(decoder)
+}
+
+/// Factorize optional attribute getter.
+fn optional_attribute(node: &Node, name: &str) -> Option<String> {
+    node.attribute(name).map(str::to_owned)
 }
 
 /// Factorize attribute getter, return an error if not found.
 fn attribute_or_error(node: &Node, name: &str) -> Result<String> {
     match node.attribute(name) {
         Some(v) => Ok(v.to_owned()),
-        None => {
-            let tag = node.tag_name().name();
-            Err(format!("{} missing a {} attribute", tag, name).into())
-        }
+        None => Err(DbError::MissingAttribute {
+            tag: node.tag_name().name().to_owned(),
+            attr: name.to_owned(),
+        }),
     }
 }
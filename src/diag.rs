@@ -0,0 +1,143 @@
+// Copyright (C) 2019 Nicolas Schodet
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Report diagnostics on standard error.
+use serde_json::json;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::Mutex;
+
+/// Whether diagnostics are output as JSON objects.
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Whether errors are output as JSON objects, whatever the diagnostics format.
+static JSON_ERRORS: AtomicBool = AtomicBool::new(false);
+
+/// Verbosity level, see `set_verbosity`.
+static VERBOSITY: AtomicU8 = AtomicU8::new(1);
+
+/// Part being processed, see `set_part`.
+static PART: Mutex<Option<String>> = Mutex::new(None);
+
+/// Diagnostics output format.
+#[derive(Clone, Copy, Debug)]
+pub enum Format {
+    /// Human readable text lines.
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+/// Names of diagnostics formats, as accepted on command line.
+pub static FORMATS: &[&str] = &["text", "json"];
+
+impl std::str::FromStr for Format {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown diagnostics format {}", s)),
+        }
+    }
+}
+
+/// Select diagnostics output format.
+pub fn set_format(format: Format) {
+    JSON.store(matches!(format, Format::Json), Ordering::Relaxed);
+}
+
+/// Output errors as JSON objects, even when other diagnostics are output as text.
+pub fn set_json_errors(json_errors: bool) {
+    JSON_ERRORS.store(json_errors, Ordering::Relaxed);
+}
+
+/// Select verbosity: 0 only outputs errors, 1 also outputs warnings and information, 2 and more
+/// also output debug messages.
+pub fn set_verbosity(verbosity: u8) {
+    VERBOSITY.store(verbosity, Ordering::Relaxed);
+}
+
+/// Set the part being processed, it is added to following JSON diagnostics until changed.
+pub fn set_part(part: Option<&str>) {
+    *PART.lock().unwrap() = part.map(str::to_owned);
+}
+
+/// Tell whether diagnostics of the given verbosity level are output.
+fn enabled(verbosity: u8) -> bool {
+    VERBOSITY.load(Ordering::Relaxed) >= verbosity
+}
+
+/// Report a debug message, only output in verbose mode.
+pub fn debug(message: &str) {
+    if enabled(2) {
+        report("debug", "debug", message);
+    }
+}
+
+/// Report an information, it is output without any level prefix in text format.
+pub fn info(message: &str) {
+    if enabled(1) {
+        report("info", "info", message);
+    }
+}
+
+/// Report a warning, `kind` is a short identifier for the warning class.
+pub fn warning(kind: &str, message: &str) {
+    if enabled(1) {
+        report("warning", kind, message);
+    }
+}
+
+/// Report an error.
+pub fn error(message: &str) {
+    report("error", "error", message);
+}
+
+/// Report an error with its class and the file it is about, if known.
+pub fn error_with(kind: &str, message: &str, path: Option<&Path>) {
+    if JSON.load(Ordering::Relaxed) || JSON_ERRORS.load(Ordering::Relaxed) {
+        let mut diag = json_diag("error", kind, message);
+        if let Some(path) = path {
+            diag["path"] = json!(path.display().to_string());
+        }
+        eprintln!("{}", diag);
+    } else {
+        report("error", kind, message);
+    }
+}
+
+/// Report a diagnostic with the selected format.
+fn report(level: &str, kind: &str, message: &str) {
+    let json_error = level == "error" && JSON_ERRORS.load(Ordering::Relaxed);
+    if JSON.load(Ordering::Relaxed) || json_error {
+        eprintln!("{}", json_diag(level, kind, message));
+    } else if level == "info" {
+        eprintln!("{}", message);
+    } else {
+        eprintln!("{}: {}", level, message);
+    }
+}
+
+/// Make a JSON diagnostic, with the part being processed if any.
+fn json_diag(level: &str, kind: &str, message: &str) -> serde_json::Value {
+    let mut diag = json!({ "level": level, "kind": kind, "message": message });
+    if let Some(part) = &*PART.lock().unwrap() {
+        diag["part"] = json!(part);
+    }
+    diag
+}
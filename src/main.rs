@@ -19,23 +19,100 @@
 //!
 //! This reads database extracted from CubeMX and produce a table of all signals that can be mapped
 //! to the microcontroller pins.  This table can be open with a spreadsheet.
+use itertools::Itertools;
+use pinmap::{db, diag, table};
+use std::collections::{BTreeMap, HashSet};
+use std::env;
 use std::error::Error;
-use std::io;
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
 use structopt::StructOpt;
 
-mod db;
-mod table;
-
 /// MCU pins mapper.
 #[derive(StructOpt, Debug)]
 struct Opt {
-    /// Database path
-    #[structopt(short = "d", long, default_value = "db", parse(from_os_str))]
-    database: PathBuf,
+    /// Database path, or CubeMX installation path, can be repeated to search several databases,
+    /// defaults to PINMAP_DB environment variable, then database from pinmap.toml, then "db"
+    #[structopt(short = "d", long, number_of_values = 1, parse(from_os_str))]
+    database: Vec<PathBuf>,
     /// Exclude component
     #[structopt(short = "x", long, number_of_values = 1)]
     exclude: Vec<String>,
+    /// Report signals removed by excludes after the table is output
+    #[structopt(long = "report-excluded")]
+    report_excluded: bool,
+    /// Exclude pins matching a name or a position, can be repeated
+    #[structopt(long = "exclude-pin", number_of_values = 1)]
+    exclude_pin: Vec<String>,
+    /// Include only this component, can be repeated
+    #[structopt(short = "i", long, number_of_values = 1)]
+    include: Vec<String>,
+    /// Include only a predefined set of signals, can be repeated
+    #[structopt(long, number_of_values = 1, raw(possible_values = "table::PRESETS"))]
+    only: Vec<table::Preset>,
+    /// Do not shorten signal names, nor factorize signals of different peripheral instances
+    #[structopt(long)]
+    expand: bool,
+    /// Additional substitution to shorten signal names, given as pattern=>replacement, can be
+    /// repeated
+    #[structopt(long, number_of_values = 1, parse(try_from_str = "table::parse_sub"))]
+    sub: Vec<(String, String)>,
+    /// Do not use default substitutions, only the ones given with --sub
+    #[structopt(long = "no-default-subs")]
+    no_default_subs: bool,
+    /// Do not shorten signal names, but still factorize signals
+    #[structopt(long = "no-shorten")]
+    no_shorten: bool,
+    /// Report the shortened names used in table, with their full names
+    #[structopt(long = "shorten-legend")]
+    shorten_legend: bool,
+    /// Additional factorization of similar signals, given as pattern=>separator, the pattern must
+    /// have one capture group for the varying part, can be repeated
+    #[structopt(long, number_of_values = 1, parse(try_from_str = "table::parse_fact"))]
+    fact: Vec<(String, String)>,
+    /// Rendering of USB OTG signal names
+    #[structopt(
+        long = "usb-naming",
+        default_value = "short",
+        raw(possible_values = "table::USB_NAMINGS")
+    )]
+    usb_naming: table::UsbNaming,
+    /// Only output I/O pins
+    #[structopt(long = "io-only")]
+    io_only: bool,
+    /// Exclude usually unneeded signals (EVENTOUT, debug, oscillators, wake up) and only output
+    /// I/O pins, more excludes can be given
+    #[structopt(long)]
+    minimal: bool,
+    /// Do not fail when the GPIO modes file is missing, signals are then output as additional
+    /// functions
+    #[structopt(long = "allow-missing-modes")]
+    allow_missing_modes: bool,
+    /// Part family used to tune default filters (F4, G4, H7...), detected from the part line by
+    /// default
+    #[structopt(long)]
+    family: Option<String>,
+    /// Keep the bare GPIO signal of pins, output as an additional function
+    #[structopt(long = "include-gpio")]
+    include_gpio: bool,
+    /// Fail on database inconsistencies, like duplicated pins or signals missing from GPIO modes
+    /// file, instead of reporting them as warnings
+    #[structopt(long)]
+    strict: bool,
+    /// Format of warnings and errors output on standard error
+    #[structopt(long, default_value = "text", raw(possible_values = "diag::FORMATS"))]
+    diagnostics: diag::Format,
+    /// Output errors as JSON objects, even with text diagnostics
+    #[structopt(long = "json-errors")]
+    json_errors: bool,
+    /// Only output errors on standard error, no warning nor information
+    #[structopt(short = "q", long)]
+    quiet: bool,
+    /// Output more diagnostics, like files read from database
+    #[structopt(short = "v", long, parse(from_occurrences), conflicts_with = "quiet")]
+    verbose: u8,
     #[structopt(subcommand)]
     command: OptCommand,
 }
@@ -44,25 +121,810 @@ struct Opt {
 enum OptCommand {
     /// Search the database for MCUs matching the given regex.
     #[structopt(name = "parts")]
-    Parts { pattern: String },
+    Parts {
+        pattern: String,
+        /// Match the full part name literally instead of using a regex
+        #[structopt(long)]
+        exact: bool,
+        /// Match the full part name with a shell-style pattern instead of a regex, "*" matches any
+        /// string and "?" any character
+        #[structopt(long, conflicts_with = "exact")]
+        glob: bool,
+        /// Only output the number of matching parts, parts are only loaded to filter by package
+        #[structopt(long)]
+        count: bool,
+        /// Only keep parts whose package matches the given regex
+        #[structopt(long)]
+        package: Option<String>,
+        /// Stop after the given number of listed parts
+        #[structopt(long)]
+        limit: Option<usize>,
+        /// Also show the GPIO IP version, this needs to read whole part files
+        #[structopt(long = "gpio-version")]
+        gpio_version: bool,
+        /// Output format
+        #[structopt(
+            short = "f",
+            long,
+            default_value = "summary",
+            raw(possible_values = "table::PARTS_FORMATS")
+        )]
+        format: table::PartsFormat,
+    },
+    /// Write an index file in each database, to speed up parts search.
+    #[structopt(name = "index")]
+    Index,
+    /// Parse every part matching the given regex, and report the ones which can not be parsed.
+    #[structopt(name = "check")]
+    Check {
+        #[structopt(default_value = ".")]
+        pattern: String,
+    },
+    /// List product lines of MCUs matching the given regex, with their number of parts.
+    #[structopt(name = "lines")]
+    Lines {
+        #[structopt(default_value = ".")]
+        pattern: String,
+    },
     /// Output a pin out table for a given part.
     #[structopt(name = "table")]
-    Table { part: String },
+    Table {
+        /// Part name, or a prefix if unambiguous, can be omitted when --file is given, "-" to read
+        /// part names from standard input, one per line, tables are then preceded by a "# part"
+        /// line unless the format is machine readable
+        part: Option<String>,
+        /// Ask which part to use when the part prefix is ambiguous
+        #[structopt(long = "select-package")]
+        select_package: bool,
+        /// Read part from the given XML file instead of the database
+        #[structopt(long, parse(from_os_str))]
+        file: Option<PathBuf>,
+        /// Read part XML from standard input instead of the database
+        #[structopt(long = "stdin-xml", conflicts_with = "file")]
+        stdin_xml: bool,
+        /// Directory containing GPIO modes files when using --file or --stdin-xml, default to the
+        /// IP directory next to the file, or in the database, then to the directory of the file,
+        /// or the database mcu directory
+        #[structopt(long = "ip-dir", parse(from_os_str))]
+        ip_dir: Option<PathBuf>,
+        /// GPIO mapping mode, detected from GPIO modes file by default
+        #[structopt(long = "gpio-mode", raw(possible_values = "db::GPIO_MODES"))]
+        gpio_mode: Option<db::GpioMode>,
+        /// Only keep signals which are available on a single pin
+        #[structopt(long = "unique-only")]
+        unique_only: bool,
+        /// Output the number of signals for each AF instead of the table
+        #[structopt(long = "count-by-af")]
+        count_by_af: bool,
+        /// Output one row for each peripheral instead of each pin, with the pins available for
+        /// each of its signals
+        #[structopt(long, raw(possible_values = "table::PIVOTS"))]
+        pivot: Option<table::Pivot>,
+        /// Output file, default to standard output, or output directory when reading part names
+        /// from standard input
+        #[structopt(short = "o", long, parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// Output format
+        #[structopt(
+            short = "f",
+            long,
+            default_value = "csv",
+            raw(possible_values = "table::FORMATS")
+        )]
+        format: table::Format,
+        /// Order of pins
+        #[structopt(
+            long,
+            default_value = "position",
+            raw(possible_values = "table::PIN_ORDERS")
+        )]
+        sort: table::PinOrder,
+        /// Do not output the header row
+        #[structopt(long = "no-header")]
+        no_header: bool,
+        /// Only output the header row
+        #[structopt(long = "schema-only")]
+        schema_only: bool,
+        /// Add a column flagging pins with debug or trace signals
+        #[structopt(long = "flag-debug")]
+        flag_debug: bool,
+        /// Add a Note column with notes read from a CSV file of pin,note lines, pins are given by
+        /// name or position
+        #[structopt(long, parse(from_os_str))]
+        annotate: Option<PathBuf>,
+        /// Add a column with the number of signals of each pin, after filtering
+        #[structopt(long = "count-signals")]
+        count_signals: bool,
+        /// Maximum number of signals in a cell, others are replaced by a count
+        #[structopt(long = "max-signals-per-cell")]
+        max_signals_per_cell: Option<usize>,
+        /// Maximum width of columns in text format, longer cells are truncated
+        #[structopt(long = "max-width")]
+        max_width: Option<usize>,
+        /// Remove signal columns which are empty for every pin
+        #[structopt(long = "prune-columns", raw(alias = r#""exclude-empty-columns""#))]
+        prune_columns: bool,
+        /// Do not output pins without any signal left after filtering
+        #[structopt(long = "hide-empty")]
+        hide_empty: bool,
+        /// Put a "-" placeholder in AF columns without signal
+        #[structopt(long = "show-empty-af")]
+        show_empty_af: bool,
+        /// Only output a signal available with several AF on the same pin in its first AF column
+        #[structopt(long = "collapse-dup-af")]
+        collapse_dup_af: bool,
+        /// Prefix additional functions with a "+" in AF tables
+        #[structopt(long = "mark-addf")]
+        mark_addf: bool,
+        /// Append the AF number to each signal in AF tables, like USART2_TX(7)
+        #[structopt(long = "inline-af")]
+        inline_af: bool,
+        /// Rendering of remaps in remap tables
+        #[structopt(
+            long = "remap-style",
+            default_value = "inline",
+            raw(possible_values = "table::REMAP_STYLES")
+        )]
+        remap_style: table::RemapStyle,
+        /// Only output AF columns in the given inclusive range, like 4-8
+        #[structopt(long = "af-range", parse(try_from_str = "table::parse_af_range"))]
+        af_range: Option<(u8, u8)>,
+        /// Keep signals of AF cells in database order instead of sorting them
+        #[structopt(long = "no-sort-cell")]
+        no_sort_cell: bool,
+    },
+    /// Compare signals available on each pin of two parts, pins are matched by position.
+    #[structopt(name = "diff")]
+    Diff { part_a: String, part_b: String },
+    /// Output the package map of a given part, sorted by position.
+    #[structopt(name = "package")]
+    Package { part: String },
+    /// Output the list of pins for every signal of a given part.
+    #[structopt(name = "signals")]
+    Signals { part: String },
+    /// Find pins carrying signals matching the given regex, in all parts matching a pattern.
+    #[structopt(name = "find-signal")]
+    FindSignal {
+        signal: String,
+        #[structopt(default_value = ".")]
+        part_pattern: String,
+    },
+    /// Check board assignments read from a CSV file of pin,signal[,mapping] lines, where mapping
+    /// is like AF7 or a remap number, and report the illegal ones.
+    #[structopt(name = "verify")]
+    Verify {
+        part: String,
+        #[structopt(parse(from_os_str))]
+        assignments: PathBuf,
+    },
+    /// Output all signals of a single pin of a given part, the pin is given by name or position.
+    #[structopt(name = "pin")]
+    Pin { part: String, pin: String },
+    /// Output statistics on pins and signals of a given part.
+    #[structopt(name = "stats")]
+    Stats {
+        part: String,
+        /// Output statistics for each GPIO port
+        #[structopt(long = "by-port")]
+        by_port: bool,
+    },
+}
+
+/// Find the database containing a part.  If not found, look for parts starting with the given
+/// name.  When there are several candidates, ask the user to choose if `select` is true and
+/// standard input is a terminal, else fail with the list of candidates.  The part is set as the
+/// diagnostics part.
+fn resolve_part<'d>(
+    databases: &'d [PathBuf],
+    part: &str,
+    select: bool,
+) -> Result<(&'d Path, String), Box<dyn Error>> {
+    diag::set_part(Some(part));
+    if let Some(database) = db::find_database(databases, part) {
+        return Ok((database, part.to_owned()));
+    }
+    let pattern = format!("^{}", regex::escape(part));
+    let mut candidates = db::list_parts(databases, &pattern)?;
+    // Only keep the first database for a part present in several ones.
+    let mut seen = HashSet::new();
+    candidates.retain(|(_, candidate)| seen.insert(candidate.clone()));
+    candidates.sort_by(|(_, a), (_, b)| a.cmp(b));
+    match candidates.len() {
+        0 => {
+            let suggestions = suggest_parts(databases, part)?;
+            Err(db::DbError::PartNotFound {
+                part: part.to_owned(),
+                suggestions,
+            }
+            .into())
+        }
+        1 => {
+            let (database, part) = candidates.remove(0);
+            diag::set_part(Some(&part));
+            diag::debug(&format!("using part {} from {}", part, database.display()));
+            Ok((database, part))
+        }
+        n if select && io::stdin().is_terminal() => {
+            for (i, (_, candidate)) in candidates.iter().enumerate() {
+                eprintln!("{}: {}", i + 1, candidate);
+            }
+            eprint!("Select part [1-{}]: ", n);
+            let mut choice = String::new();
+            io::stdin().read_line(&mut choice)?;
+            let choice = choice
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|choice| (1..=n).contains(choice))
+                .ok_or("invalid choice")?;
+            let (database, part) = candidates.swap_remove(choice - 1);
+            diag::set_part(Some(&part));
+            Ok((database, part))
+        }
+        _ => {
+            let candidates = candidates.into_iter().map(|(_, candidate)| candidate);
+            Err(db::DbError::AmbiguousPart {
+                part: part.to_owned(),
+                candidates: candidates.collect(),
+            }
+            .into())
+        }
+    }
+}
+
+/// Suggest parts with a name close to the given one, looking for parts containing the longest
+/// possible start of the name, ignoring case.
+fn suggest_parts(databases: &[PathBuf], part: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    const MAX_SUGGESTIONS: usize = 5;
+    let min_len = (part.len() / 2).max(3).min(part.len());
+    for len in (min_len..=part.len()).rev() {
+        if !part.is_char_boundary(len) {
+            continue;
+        }
+        let pattern = format!("(?i){}", regex::escape(&part[..len]));
+        let found = db::list_parts(databases, &pattern)?;
+        if !found.is_empty() {
+            let found = found.into_iter().map(|(_, part)| part).sorted().dedup();
+            return Ok(found.take(MAX_SUGGESTIONS).collect());
+        }
+    }
+    Ok(Vec::new())
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
+/// Suggest pins with a name close to the given one, looking for pins starting with the longest
+/// possible start of the name, ignoring case.
+fn suggest_pins(part_info: &db::PartInfo, pin: &str) -> Vec<String> {
+    const MAX_SUGGESTIONS: usize = 5;
+    let pin = pin.to_uppercase();
+    let min_len = 2.min(pin.len());
+    for len in (min_len..=pin.len()).rev() {
+        if !pin.is_char_boundary(len) {
+            continue;
+        }
+        let found = part_info
+            .pins
+            .iter()
+            .map(|p| p.name.as_str())
+            .filter(|name| name.to_uppercase().starts_with(&pin[..len]))
+            .unique()
+            .take(MAX_SUGGESTIONS)
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        if !found.is_empty() {
+            return found;
+        }
+    }
+    Vec::new()
+}
+
+/// Translate a shell-style pattern to an anchored regex.
+fn glob_to_regex(glob: &str) -> String {
+    let mut re = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            c => re.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    re.push('$');
+    re
+}
+
+/// Check part consistency, report problems as warnings, or fail if `strict` is true.
+fn check_part(part_info: &db::PartInfo, strict: bool) -> Result<(), Box<dyn Error>> {
+    let problems = part_info.validate();
+    if strict && !problems.is_empty() {
+        return Err(problems.join("\n").into());
+    }
+    for problem in problems {
+        diag::warning("inconsistent-part", &problem);
+    }
+    Ok(())
+}
+
+/// Find default databases when none is given on command line: use `PINMAP_DB` environment
+/// variable, which can list several paths, else `database` from the first `pinmap.toml` found in
+/// current directory or in user configuration directory, else `db`.
+fn default_databases() -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    if let Some(paths) = env::var_os("PINMAP_DB") {
+        return Ok(env::split_paths(&paths).collect());
+    }
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+    let configs = [Some(PathBuf::new()), config_dir.map(|d| d.join("pinmap"))];
+    for dir in configs.iter().flatten() {
+        let path = dir.join("pinmap.toml");
+        let config = match fs::read_to_string(&path) {
+            Ok(config) => config,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(format!("{}: {}", path.display(), e).into()),
+        };
+        let config = config
+            .parse::<toml::Value>()
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        // Relative paths are relative to the configuration file.
+        let database = |value: &toml::Value| {
+            value.as_str().map(|d| dir.join(d)).ok_or_else(|| {
+                format!(
+                    "{}: database should be a string or a list of strings",
+                    path.display()
+                )
+            })
+        };
+        match config.get("database") {
+            None => (),
+            Some(toml::Value::Array(values)) => {
+                return Ok(values.iter().map(database).collect::<Result<_, _>>()?);
+            }
+            Some(value) => return Ok(vec![database(value)?]),
+        }
+    }
+    Ok(vec![PathBuf::from("db")])
+}
+
+fn main() {
     let opt = Opt::from_args();
+    diag::set_format(opt.diagnostics);
+    diag::set_json_errors(opt.json_errors);
+    diag::set_verbosity(if opt.quiet { 0 } else { 1 + opt.verbose });
+    if let Err(e) = run(opt) {
+        match e.downcast_ref::<db::DbError>() {
+            Some(db_error) => diag::error_with(db_error.kind(), &e.to_string(), db_error.path()),
+            None => diag::error_with("error", &e.to_string(), None),
+        }
+        process::exit(1);
+    }
+}
+
+fn run(mut opt: Opt) -> Result<(), Box<dyn Error>> {
+    if opt.database.is_empty() {
+        opt.database = default_databases()?;
+    }
+    opt.database = opt.database.iter().map(|d| db::database_root(d)).collect();
+    let mut loader = db::Database::new();
+    if opt.include_gpio {
+        loader.include_gpio();
+    }
     match opt.command {
-        OptCommand::Parts { pattern } => {
-            for part in db::list_parts(&opt.database, &pattern)? {
-                let part_info = db::PartInfo::new(&opt.database, &part)?;
-                println!("{}", part_info.summary());
+        OptCommand::Lines { pattern } => {
+            let parts = db::list_parts(&opt.database, &pattern)?;
+            let mut lines = BTreeMap::new();
+            for ((_, part), header) in parts.iter().zip(loader.headers(&parts, false)) {
+                match header {
+                    Ok(header) => *lines.entry(header.line).or_insert(0) += 1,
+                    Err(e) => {
+                        diag::set_part(Some(part));
+                        diag::warning("bad-part", &format!("{}: {}", part, e));
+                    }
+                }
+            }
+            diag::set_part(None);
+            table::write_lines(&lines, io::stdout())?;
+        }
+        OptCommand::Index => {
+            for (i, database) in opt.database.iter().enumerate() {
+                if !opt.database[..i].contains(database) {
+                    let count = db::write_index(database)?;
+                    diag::info(&format!("{}: {} parts indexed", database.display(), count));
+                }
+            }
+        }
+        OptCommand::Check { pattern } => {
+            let parts = db::list_parts(&opt.database, &pattern)?;
+            let mut failed = 0;
+            for (database, part) in &parts {
+                diag::set_part(Some(part));
+                let part_info = loader.part(database, part, opt.allow_missing_modes);
+                let e = match part_info {
+                    Ok(part_info) => match check_part(&part_info, opt.strict) {
+                        Ok(()) => continue,
+                        Err(e) => e,
+                    },
+                    Err(e) => e.into(),
+                };
+                failed += 1;
+                let message = format!("{}: {}", part, e);
+                match e.downcast_ref::<db::DbError>() {
+                    Some(db_error) => diag::error_with(db_error.kind(), &message, db_error.path()),
+                    None => diag::error(&message),
+                }
+            }
+            diag::set_part(None);
+            diag::info(&format!(
+                "{} parts checked, {} succeeded, {} failed",
+                parts.len(),
+                parts.len() - failed,
+                failed
+            ));
+            if failed != 0 {
+                return Err(format!("{} parts failed", failed).into());
+            }
+        }
+        OptCommand::Diff { part_a, part_b } => {
+            let (database_a, part_a) = resolve_part(&opt.database, &part_a, false)?;
+            let (database_b, part_b) = resolve_part(&opt.database, &part_b, false)?;
+            diag::set_part(Some(&part_a));
+            let part_info_a = loader.part(database_a, &part_a, opt.allow_missing_modes)?;
+            check_part(&part_info_a, opt.strict)?;
+            diag::set_part(Some(&part_b));
+            let part_info_b = loader.part(database_b, &part_b, opt.allow_missing_modes)?;
+            check_part(&part_info_b, opt.strict)?;
+            diag::set_part(None);
+            table::write_diff(&part_info_a, &part_info_b, io::stdout())?;
+        }
+        OptCommand::Package { part } => {
+            let (database, part) = resolve_part(&opt.database, &part, false)?;
+            let part_info = loader.part(database, &part, opt.allow_missing_modes)?;
+            check_part(&part_info, opt.strict)?;
+            table::write_package(&part_info, io::stdout())?;
+        }
+        OptCommand::Signals { part } => {
+            let (database, part) = resolve_part(&opt.database, &part, false)?;
+            let part_info = loader.part(database, &part, opt.allow_missing_modes)?;
+            check_part(&part_info, opt.strict)?;
+            table::write_signals(&part_info, io::stdout())?;
+        }
+        OptCommand::FindSignal {
+            signal,
+            part_pattern,
+        } => {
+            let signal = regex::Regex::new(&signal)?;
+            let parts = db::list_parts(&opt.database, &part_pattern)?;
+            let allow_missing_modes = opt.allow_missing_modes;
+            // Do not stop search on a bad part.
+            let part_infos = parts.iter().filter_map(|(database, part)| {
+                diag::set_part(Some(part));
+                match loader.part(database, part, allow_missing_modes) {
+                    Ok(part_info) => Some(part_info),
+                    Err(e) => {
+                        diag::warning("bad-part", &format!("{}: {}", part, e));
+                        None
+                    }
+                }
+            });
+            table::write_signal_search(part_infos, &signal, io::stdout())?;
+            diag::set_part(None);
+        }
+        OptCommand::Verify { part, assignments } => {
+            let assignments = table::read_assignments(&assignments)?;
+            let (database, part) = resolve_part(&opt.database, &part, false)?;
+            let part_info = loader.part(database, &part, opt.allow_missing_modes)?;
+            check_part(&part_info, opt.strict)?;
+            let problems = part_info.verify(&assignments);
+            for problem in &problems {
+                diag::error(problem);
+            }
+            if !problems.is_empty() {
+                return Err(format!("{} illegal assignments", problems.len()).into());
+            }
+            diag::info(&format!("{} assignments verified", assignments.len()));
+        }
+        OptCommand::Pin { part, pin } => {
+            let (database, part) = resolve_part(&opt.database, &part, false)?;
+            let part_info = loader.part(database, &part, opt.allow_missing_modes)?;
+            check_part(&part_info, opt.strict)?;
+            match part_info.find_pin(&pin) {
+                Some(pin_info) => table::write_pin(pin_info, io::stdout())?,
+                None => {
+                    let suggestions = suggest_pins(&part_info, &pin);
+                    if suggestions.is_empty() {
+                        return Err(format!("pin {} not found", pin).into());
+                    }
+                    let suggestions = suggestions.join(", ");
+                    return Err(
+                        format!("pin {} not found, did you mean: {}?", pin, suggestions).into(),
+                    );
+                }
             }
         }
-        OptCommand::Table { part } => {
-            let part_info = db::PartInfo::new(&opt.database, &part)?;
-            let filter = table::SignalFilter::new(&opt.exclude)?;
-            table::write_pin_out(&part_info, io::stdout(), &filter)?;
+        OptCommand::Stats { part, by_port } => {
+            let (database, part) = resolve_part(&opt.database, &part, false)?;
+            let part_info = loader.part(database, &part, opt.allow_missing_modes)?;
+            check_part(&part_info, opt.strict)?;
+            table::write_stats(&part_info, io::stdout(), by_port)?;
+        }
+        OptCommand::Parts {
+            pattern,
+            exact,
+            glob,
+            count,
+            package,
+            limit,
+            gpio_version,
+            format,
+        } => {
+            let tag = opt.database.len() > 1;
+            let pattern = if exact {
+                format!("^{}$", regex::escape(&pattern))
+            } else if glob {
+                glob_to_regex(&pattern)
+            } else {
+                pattern
+            };
+            let package = match package {
+                Some(package) => Some(regex::Regex::new(&package)?),
+                None => None,
+            };
+            let parts = match db::list_parts(&opt.database, &pattern) {
+                Err(db::DbError::Pattern(e)) if !glob && pattern.contains(['*', '?']) => {
+                    return Err(format!("{}\nhint: use --glob for shell-style patterns", e).into());
+                }
+                r => r?,
+            };
+            if count && package.is_none() && limit.is_none() {
+                println!("{}", parts.len());
+                return Ok(());
+            }
+            let mut matched = Vec::new();
+            let mut truncated = false;
+            // Read headers by chunks, so that a limited search does not read every part.
+            let chunk = limit.map_or(parts.len(), |limit| limit + 1).max(1);
+            'search: for parts in parts.chunks(chunk) {
+                let headers = loader.headers(parts, gpio_version);
+                for ((database, part), header) in parts.iter().zip(headers) {
+                    // Do not stop listing on a bad part.
+                    let header = match header {
+                        Ok(header) => header,
+                        Err(e) => {
+                            diag::set_part(Some(part));
+                            diag::warning("bad-part", &format!("{}: {}", part, e));
+                            diag::set_part(None);
+                            continue;
+                        }
+                    };
+                    if let Some(package) = &package {
+                        let found = header.package.as_ref();
+                        if !found.is_some_and(|found| package.is_match(found)) {
+                            continue;
+                        }
+                    }
+                    matched.push((*database, header));
+                    // Look for one more part to tell whether the list is truncated.
+                    if limit.is_some_and(|limit| matched.len() > limit) {
+                        matched.pop();
+                        truncated = true;
+                        break 'search;
+                    }
+                }
+            }
+            let more = if truncated { "+" } else { "" };
+            if count {
+                println!("{}{}", matched.len(), more);
+            } else {
+                table::write_parts(&matched, io::stdout(), format, tag, gpio_version)?;
+                diag::info(&format!("{}{} parts matched", matched.len(), more));
+            }
+        }
+        OptCommand::Table {
+            part,
+            select_package,
+            file,
+            stdin_xml,
+            ip_dir,
+            gpio_mode,
+            unique_only,
+            count_by_af,
+            pivot,
+            output,
+            format,
+            sort,
+            no_header,
+            schema_only,
+            flag_debug,
+            annotate,
+            count_signals,
+            max_signals_per_cell,
+            max_width,
+            prune_columns,
+            hide_empty,
+            show_empty_af,
+            collapse_dup_af,
+            mark_addf,
+            inline_af,
+            remap_style,
+            af_range,
+            no_sort_cell,
+        } => {
+            if let Some(gpio_mode) = gpio_mode {
+                loader.force_gpio_mode(gpio_mode);
+            }
+            let user_exclude = opt.exclude.clone();
+            let mut exclude = opt.exclude;
+            if opt.minimal {
+                exclude.extend(table::MINIMAL_EXCLUDES.iter().map(|x| x.to_string()));
+            }
+            let filter_options = table::FilterOptions {
+                exclude,
+                include: opt.include,
+                only: opt.only,
+                io_only: opt.io_only || opt.minimal,
+                exclude_pin: opt.exclude_pin,
+                expand: opt.expand,
+                usb_naming: opt.usb_naming,
+                family: opt.family,
+                subs: opt.sub,
+                no_default_subs: opt.no_default_subs,
+                no_shorten: opt.no_shorten,
+                facts: opt.fact,
+            };
+            let options = table::TableOptions {
+                format,
+                header: !no_header,
+                schema_only,
+                flag_debug,
+                count_signals,
+                max_signals_per_cell,
+                max_width,
+                prune_columns,
+                hide_empty,
+                show_empty_af,
+                collapse_dup_af,
+                mark_addf,
+                inline_af,
+                remap_style,
+                af_range,
+                sort_cell: !no_sort_cell,
+                notes: match annotate {
+                    Some(annotate) => Some(table::read_notes(&annotate)?),
+                    None => None,
+                },
+            };
+            let (strict, report_excluded, shorten_legend) =
+                (opt.strict, opt.report_excluded, opt.shorten_legend);
+            let write_table = |mut part_info: db::PartInfo<'_>,
+                               writer: Box<dyn Write>|
+             -> Result<(), Box<dyn Error>> {
+                check_part(&part_info, strict)?;
+                if unique_only {
+                    part_info.retain_unique_signals();
+                }
+                table::sort_pins(&mut part_info, sort);
+                let filter_options = table::FilterOptions {
+                    family: filter_options.family.clone().or_else(|| part_info.family()),
+                    ..filter_options.clone()
+                };
+                let filter = table::SignalFilter::new(&filter_options)?;
+                if count_by_af {
+                    table::write_af_histogram(&part_info, writer)?;
+                } else if let Some(table::Pivot::Peripheral) = pivot {
+                    table::write_peripherals(&part_info, writer, &filter)?;
+                } else {
+                    table::write_pin_out(&part_info, writer, &filter, &options)?;
+                }
+                if report_excluded {
+                    for (peripheral, names) in filter.excluded_signals() {
+                        diag::info(&format!("excluded {}: {}", peripheral, names.join(" ")));
+                    }
+                }
+                if shorten_legend {
+                    for (short, full) in filter.applied_subs() {
+                        diag::info(&format!("{} = {}", short, full));
+                    }
+                }
+                // Only report user patterns, default ones are not expected to always match.
+                let unmatched = filter.unmatched_excludes().into_iter();
+                let unmatched = unmatched.filter(|x| user_exclude.iter().any(|u| u == x));
+                let unmatched = unmatched.collect::<Vec<_>>().join(", ");
+                if !unmatched.is_empty() {
+                    diag::warning(
+                        "unmatched-exclude",
+                        &format!("exclude patterns matching no signal: {}", unmatched),
+                    );
+                }
+                Ok(())
+            };
+            let create = |output: &Path| -> Result<Box<dyn Write>, String> {
+                let file = File::create(output)
+                    .map_err(|e| format!("can not create {}: {}", output.display(), e))?;
+                Ok(Box::new(file))
+            };
+            if file.is_none() && !stdin_xml && part.as_deref() == Some("-") {
+                // Batch mode, do not stop on a failing part, report failures at the end.
+                let mut parts = String::new();
+                io::stdin().read_to_string(&mut parts)?;
+                let (extension, part_header) = if count_by_af || pivot.is_some() {
+                    ("csv", true)
+                } else {
+                    (format.extension(), !format.is_machine_readable())
+                };
+                let (databases, allow_missing_modes) = (&opt.database, opt.allow_missing_modes);
+                let mut failed = Vec::new();
+                for part in parts.lines().map(str::trim).filter(|part| !part.is_empty()) {
+                    let mut batch_table = || -> Result<(), Box<dyn Error>> {
+                        let (database, part) = resolve_part(databases, part, false)?;
+                        let part_info = loader.part(database, &part, allow_missing_modes)?;
+                        let writer = match &output {
+                            Some(output) => {
+                                create(&output.join(format!("{}.{}", part, extension)))?
+                            }
+                            None => {
+                                if part_header {
+                                    println!("# {}", part);
+                                }
+                                Box::new(io::stdout())
+                            }
+                        };
+                        write_table(part_info, writer)
+                    };
+                    if let Err(e) = batch_table() {
+                        failed.push((part, e));
+                    }
+                }
+                for (part, e) in &failed {
+                    diag::set_part(Some(part));
+                    let message = format!("{}: {}", part, e);
+                    match e.downcast_ref::<db::DbError>() {
+                        Some(db_error) => {
+                            diag::error_with(db_error.kind(), &message, db_error.path())
+                        }
+                        None => diag::error(&message),
+                    }
+                }
+                diag::set_part(None);
+                if !failed.is_empty() {
+                    return Err(format!("{} parts failed", failed.len()).into());
+                }
+                return Ok(());
+            }
+            let resolved_part;
+            let mut xml = String::new();
+            let part_info = if let Some(file) = &file {
+                let part = match &part {
+                    Some(part) => part.as_str(),
+                    None => file
+                        .file_name()
+                        .and_then(|name| name.to_str())
+                        .and_then(|name| name.split('.').next())
+                        .ok_or("can not find part name from file name")?,
+                };
+                diag::set_part(Some(part));
+                let ip_dirs = match ip_dir {
+                    Some(ip_dir) => vec![ip_dir],
+                    None => db::ip_dirs(file.parent().unwrap_or_else(|| Path::new(""))),
+                };
+                loader.part_from_file(file, &ip_dirs, part, opt.allow_missing_modes)?
+            } else if stdin_xml {
+                let part = part.as_deref().unwrap_or("stdin");
+                diag::set_part(Some(part));
+                let ip_dirs = match ip_dir {
+                    Some(ip_dir) => vec![ip_dir],
+                    None => db::ip_dirs(&opt.database[0].join("mcu")),
+                };
+                io::stdin().read_to_string(&mut xml)?;
+                loader.part_from_xml(&xml, &ip_dirs, part, opt.allow_missing_modes)?
+            } else {
+                let part = part.as_ref().ok_or("missing part name")?;
+                let (database, part) = resolve_part(&opt.database, part, select_package)?;
+                resolved_part = part;
+                loader.part(database, &resolved_part, opt.allow_missing_modes)?
+            };
+            let writer = match output {
+                Some(output) => create(&output)?,
+                None => Box::new(io::stdout()),
+            };
+            write_table(part_info, writer)?;
         }
     }
     Ok(())
@@ -19,22 +19,460 @@
 use crate::db;
 use itertools::Itertools;
 use regex::{Regex, RegexSet};
+use serde_json::json;
+use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::collections::hash_set::HashSet;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::error::Error;
 use std::io::Write;
+use std::path::Path;
 use std::result::Result as StdResult;
 
 type Result<T> = StdResult<T, Box<dyn Error>>;
 
+/// Excludes used by the minimal preset, to remove signals which are rarely useful when assigning
+/// pins: EVENTOUT, debug and trace, oscillators and wake up.
+pub static MINIMAL_EXCLUDES: &[&str] = &[
+    "EVENTOUT",
+    "SYS_(?:JT|SW|TRACE)[A-Z0-9-]*",
+    "RCC_OSC(?:32)?",
+    "SYS_WKUP[0-9]*",
+];
+
+/// Output format.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Format {
+    /// CSV table, with one row for each pin.
+    #[default]
+    Csv,
+    /// CSV table, with one row for each pin and signal.
+    Long,
+    /// Tab separated table, with one row for each pin.
+    Tsv,
+    /// GitHub flavored Markdown table, with one row for each pin.
+    Markdown,
+    /// InfluxDB line protocol, with one point for each pin.
+    Influx,
+    /// JSON document with all part information, signals are not shortened nor factorized.
+    Json,
+    /// JSON document listing, for each pin, the group of mutually exclusive signals.
+    Exclusive,
+    /// Text table with aligned columns, for terminal viewing.
+    Text,
+    /// C header with a macro giving the AF number, or remaps, of each signal on each pin.
+    CHeader,
+}
+
+/// Names of output formats, as accepted on command line.
+pub static FORMATS: &[&str] = &[
+    "csv",
+    "long",
+    "tsv",
+    "markdown",
+    "influx",
+    "json",
+    "exclusive",
+    "text",
+    "c-header",
+];
+
+/// Pin out table output options.
+#[derive(Debug)]
+pub struct TableOptions {
+    /// Output format.
+    pub format: Format,
+    /// Output a header row before the table.
+    pub header: bool,
+    /// Only output the header row, without any pin.
+    pub schema_only: bool,
+    /// Add a column telling whether the pin hosts debug or trace signals.
+    pub flag_debug: bool,
+    /// Add a column with the number of signals of the pin, after filtering.
+    pub count_signals: bool,
+    /// Maximum number of signals in a cell, not used for long format.
+    pub max_signals_per_cell: Option<usize>,
+    /// Maximum width of a column, longer cells are truncated with an ellipsis.  Only used for
+    /// text format.
+    pub max_width: Option<usize>,
+    /// Remove signal columns which are empty for every pin.
+    pub prune_columns: bool,
+    /// Remove pins without any signal left after filtering.
+    pub hide_empty: bool,
+    /// Mark AF without any signal with a placeholder, only used for AF based parts in CSV, TSV
+    /// and Markdown formats.
+    pub show_empty_af: bool,
+    /// Output a signal available with several AF on the same pin only in the first AF column,
+    /// else it is output in every column.  In both cases, it is annotated with the AF list.
+    pub collapse_dup_af: bool,
+    /// Prefix additional functions with a "+" so that they can not be mistaken for AF signals,
+    /// only used for AF based parts.
+    pub mark_addf: bool,
+    /// Annotate each signal with its AF number, like `USART2_TX(7)`, so that cells can be read
+    /// without their column.  Only used for AF based parts.
+    pub inline_af: bool,
+    /// Rendering of remaps, only used for remap based parts.
+    pub remap_style: RemapStyle,
+    /// Only output AF columns in this inclusive range, the additional functions column is always
+    /// output.  Only used for AF based parts.
+    pub af_range: Option<(u8, u8)>,
+    /// Sort signals in AF cells, else they are kept in database order.  Remap cells are always
+    /// sorted.
+    pub sort_cell: bool,
+    /// Notes to output in a last extra column, indexed by pin name, pin name without its
+    /// additional function, or position, see `read_notes`.
+    pub notes: Option<HashMap<String, String>>,
+}
+
+impl Default for TableOptions {
+    /// CSV table with a header row and sorted cells, without any extra column or annotation.
+    fn default() -> TableOptions {
+        TableOptions {
+            format: Format::default(),
+            header: true,
+            schema_only: false,
+            flag_debug: false,
+            count_signals: false,
+            max_signals_per_cell: None,
+            max_width: None,
+            prune_columns: false,
+            hide_empty: false,
+            show_empty_af: false,
+            collapse_dup_af: false,
+            mark_addf: false,
+            inline_af: false,
+            remap_style: RemapStyle::default(),
+            af_range: None,
+            sort_cell: true,
+            notes: None,
+        }
+    }
+}
+
+/// Rendering of USB OTG signal names.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum UsbNaming {
+    /// Only keep speed, `USB_OTG_FS_DP` becomes `FS_DP`.
+    #[default]
+    Short,
+    /// Keep OTG and speed, `USB_OTG_FS_DP` becomes `OTG_FS_DP`.
+    Otg,
+    /// Keep name verbatim.
+    Full,
+}
+
+/// Names of USB OTG renderings, as accepted on command line.
+pub static USB_NAMINGS: &[&str] = &["short", "otg", "full"];
+
+impl std::str::FromStr for UsbNaming {
+    type Err = String;
+    fn from_str(s: &str) -> StdResult<UsbNaming, String> {
+        match s {
+            "short" => Ok(UsbNaming::Short),
+            "otg" => Ok(UsbNaming::Otg),
+            "full" => Ok(UsbNaming::Full),
+            _ => Err(format!("unknown USB naming {}", s)),
+        }
+    }
+}
+
+/// Parts list output format.
+#[derive(Clone, Copy, Debug)]
+pub enum PartsFormat {
+    /// One-line summary for each part.
+    Summary,
+    /// CSV table with part, line and package columns.
+    Csv,
+}
+
+/// Names of parts list output formats, as accepted on command line.
+pub static PARTS_FORMATS: &[&str] = &["summary", "csv"];
+
+impl std::str::FromStr for PartsFormat {
+    type Err = String;
+    fn from_str(s: &str) -> StdResult<PartsFormat, String> {
+        match s {
+            "summary" => Ok(PartsFormat::Summary),
+            "csv" => Ok(PartsFormat::Csv),
+            _ => Err(format!("unknown parts format {}", s)),
+        }
+    }
+}
+
+/// Table pivot, to output rows for something else than pins.
+#[derive(Clone, Copy, Debug)]
+pub enum Pivot {
+    /// One row for each peripheral instance.
+    Peripheral,
+}
+
+/// Names of table pivots, as accepted on command line.
+pub static PIVOTS: &[&str] = &["peripheral"];
+
+impl std::str::FromStr for Pivot {
+    type Err = String;
+    fn from_str(s: &str) -> StdResult<Pivot, String> {
+        match s {
+            "peripheral" => Ok(Pivot::Peripheral),
+            _ => Err(format!("unknown pivot {}", s)),
+        }
+    }
+}
+
+/// Order of pins in output.
+#[derive(Clone, Copy, Debug)]
+pub enum PinOrder {
+    /// Package order, sorted by position.
+    Position,
+    /// GPIO pins sorted by port and number, followed by other pins sorted by name.
+    Name,
+}
+
+/// Names of pin orders, as accepted on command line.
+pub static PIN_ORDERS: &[&str] = &["position", "name"];
+
+impl std::str::FromStr for PinOrder {
+    type Err = String;
+    fn from_str(s: &str) -> StdResult<PinOrder, String> {
+        match s {
+            "position" => Ok(PinOrder::Position),
+            "name" => Ok(PinOrder::Name),
+            _ => Err(format!("unknown pin order {}", s)),
+        }
+    }
+}
+
+/// Rendering of remaps in remap based part tables.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum RemapStyle {
+    /// Remaps listed after the signal name, `TIM2_CH1(0,2)`.
+    #[default]
+    Inline,
+    /// Only signal names, `TIM2_CH1`.
+    Bare,
+    /// Remaps listed with their remap group, `TIM2_CH1(TIM2:0,2)`.  A signal can be available
+    /// through the remaps of several peripherals.
+    Groups,
+    /// One column for each peripheral and remap, `TIM2_CH1` is output in both `T2(0)` and
+    /// `T2(2)` columns.
+    Columns,
+}
+
+/// Names of remap styles, as accepted on command line.
+pub static REMAP_STYLES: &[&str] = &["inline", "bare", "groups", "columns"];
+
+impl std::str::FromStr for RemapStyle {
+    type Err = String;
+    fn from_str(s: &str) -> StdResult<RemapStyle, String> {
+        match s {
+            "inline" => Ok(RemapStyle::Inline),
+            "bare" => Ok(RemapStyle::Bare),
+            "groups" => Ok(RemapStyle::Groups),
+            "columns" => Ok(RemapStyle::Columns),
+            _ => Err(format!("unknown remap style {}", s)),
+        }
+    }
+}
+
+/// Predefined sets of signals to include.
+#[derive(Clone, Copy, Debug)]
+pub enum Preset {
+    /// ADC, DAC, comparators and operational amplifiers.
+    Analog,
+    /// SPI, I2C, I2S, USART, CAN and USB.
+    Comms,
+    /// Timers.
+    Timers,
+}
+
+/// Names of presets, as accepted on command line.
+pub static PRESETS: &[&str] = &["analog", "comms", "timers"];
+
+impl Preset {
+    /// Include patterns for this preset, matching both full and shortened signal names.
+    fn includes(self) -> &'static [&'static str] {
+        match self {
+            Preset::Analog => &["ADC", "DAC", "COMP", "OPAMP"],
+            Preset::Comms => &[
+                "S(?:PI)?",
+                "I2[CS]",
+                "(?:LP)?U(?:S?ART)?",
+                "(?:FD)?CAN",
+                "C",
+                "USB(?:_OTG)?",
+                "(?:OTG_)?[FH]S",
+            ],
+            Preset::Timers => &["(?:HR|LP)?T(?:IM)?"],
+        }
+    }
+}
+
+impl std::str::FromStr for Preset {
+    type Err = String;
+    fn from_str(s: &str) -> StdResult<Preset, String> {
+        match s {
+            "analog" => Ok(Preset::Analog),
+            "comms" => Ok(Preset::Comms),
+            "timers" => Ok(Preset::Timers),
+            _ => Err(format!("unknown preset {}", s)),
+        }
+    }
+}
+
+/// Families having an HRTIM peripheral.
+static HRTIM_FAMILIES: &[&str] = &["F3", "G4", "H7"];
+
+/// Options used to prepare a signal filter.
+#[derive(Clone, Debug, Default)]
+pub struct FilterOptions {
+    /// Signals to exclude, each pattern must match a signal prefix.
+    pub exclude: Vec<String>,
+    /// Signals to include, if not empty, only signals matching one of the patterns are kept,
+    /// unless excluded.
+    pub include: Vec<String>,
+    /// Predefined sets of signals to include, in addition to `include`.
+    pub only: Vec<Preset>,
+    /// Only keep I/O pins.
+    pub io_only: bool,
+    /// Pins to exclude, each pattern must match a whole pin name, pin name without its
+    /// descriptive suffix, or position.
+    pub exclude_pin: Vec<String>,
+    /// Keep full peripheral instance names, signal names are not shortened and signals from
+    /// different instances are not factorized.  Excludes must then use full names.
+    pub expand: bool,
+    /// Rendering of USB OTG signal names.
+    pub usb_naming: UsbNaming,
+    /// Part family, used to select default substitutions, see `db::PartInfo::family`.  When
+    /// unknown, all substitutions are used.
+    pub family: Option<String>,
+    /// Additional substitutions to shorten signal names, with pattern and replacement.  Like
+    /// default ones, a pattern must match a signal prefix followed by a digit or an underscore.
+    pub subs: Vec<(String, String)>,
+    /// Do not use default substitutions, only additional ones.
+    pub no_default_subs: bool,
+    /// Do not shorten signal names at all, neither with default nor additional substitutions.
+    /// Unlike `expand`, signals from different instances are still factorized.
+    pub no_shorten: bool,
+    /// Additional factorizations, with pattern and separator.  A pattern must contain exactly one
+    /// capture group matching the varying part, signals only differing by this part are merged
+    /// into one, joining the varying parts with the separator.  For example `T\d_(CH\d)` with
+    /// `/` turns `T1_CH1` and `T1_CH2` into `T1_CH1/CH2`.
+    pub facts: Vec<(String, String)>,
+}
+
+/// Parse a substitution given as `pattern=>replacement`.
+pub fn parse_sub(s: &str) -> StdResult<(String, String), String> {
+    match s.find("=>") {
+        Some(i) => Ok((s[..i].to_owned(), s[i + 2..].to_owned())),
+        None => Err(format!(
+            "bad substitution {}, expected pattern=>replacement",
+            s
+        )),
+    }
+}
+
+/// Read pin notes from a CSV file without header, with a pin name or position followed by the
+/// note on each line.
+pub fn read_notes(path: &Path) -> Result<HashMap<String, String>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_path(path)
+        .map_err(|e| format!("can not read notes {}: {}", path.display(), e))?;
+    let mut notes = HashMap::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| format!("bad notes {}: {}", path.display(), e))?;
+        match (record.get(0), record.get(1)) {
+            (Some(pin), Some(note)) => {
+                notes.insert(pin.trim().to_owned(), note.trim().to_owned());
+            }
+            _ => {
+                let line = record.position().map_or(0, |p| p.line());
+                return Err(
+                    format!("bad notes {}:{}: expected pin,note", path.display(), line).into(),
+                );
+            }
+        }
+    }
+    Ok(notes)
+}
+
+/// Read board assignments from a CSV file without header, with a pin name or position, a signal
+/// name, and optionally the expected mapping on each line, see `db::Assignment`.
+pub fn read_assignments(path: &Path) -> Result<Vec<db::Assignment>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_path(path)
+        .map_err(|e| format!("can not read assignments {}: {}", path.display(), e))?;
+    let mut assignments = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| format!("bad assignments {}: {}", path.display(), e))?;
+        let field = |i| record.get(i).map(str::trim).filter(|x| !x.is_empty());
+        match (field(0), field(1), record.len()) {
+            (Some(pin), Some(signal), 2..=3) => assignments.push(db::Assignment {
+                pin: pin.to_owned(),
+                signal: signal.to_owned(),
+                mapping: field(2).map(str::to_owned),
+            }),
+            _ => {
+                let line = record.position().map_or(0, |p| p.line());
+                let e = "expected pin,signal[,mapping]";
+                return Err(format!("bad assignments {}:{}: {}", path.display(), line, e).into());
+            }
+        }
+    }
+    Ok(assignments)
+}
+
+/// Parse an inclusive AF range given as `first-last`, or a single AF.
+pub fn parse_af_range(s: &str) -> StdResult<(u8, u8), String> {
+    let af = |x: &str| x.trim().parse::<u8>().ok();
+    let range = match s.find('-') {
+        Some(i) => af(&s[..i]).zip(af(&s[i + 1..])),
+        None => af(s).map(|x| (x, x)),
+    };
+    match range {
+        Some((first, last)) if first <= last => Ok((first, last)),
+        _ => Err(format!("bad AF range {}, expected first-last", s)),
+    }
+}
+
+/// Parse a factorization given as `pattern=>separator`.
+pub fn parse_fact(s: &str) -> StdResult<(String, String), String> {
+    match s.find("=>") {
+        Some(i) => Ok((s[..i].to_owned(), s[i + 2..].to_owned())),
+        None => Err(format!(
+            "bad factorization {}, expected pattern=>separator",
+            s
+        )),
+    }
+}
+
 /// Filter signals to reduce pin out table size.
 pub struct SignalFilter {
     /// Signals to exclude from table.
     excludes: RegexSet,
-    /// Substitutions to shorten signal names.
-    subs: Vec<Regex>,
+    /// Exclude patterns, as given in options.
+    exclude_patterns: Vec<String>,
+    /// For each exclude, whether it matched a signal, `None` until signals are filtered.
+    exclude_hits: RefCell<Option<Vec<bool>>>,
+    /// Signals removed by excludes.
+    excluded: RefCell<BTreeSet<String>>,
+    /// Signals to include in table, if not empty, other signals are excluded.
+    includes: RegexSet,
+    /// Only keep I/O pins.
+    io_only: bool,
+    /// Pins to exclude from table.
+    exclude_pins: RegexSet,
+    /// Substitutions to shorten signal names, with the replacement.
+    subs: Vec<(Regex, String)>,
+    /// Shortened prefixes produced by substitutions, with the original prefix.
+    sub_hits: RefCell<BTreeSet<(String, String)>>,
+    /// Differential inputs, positive and negative inputs of the same channel are merged.
+    diff_pairs: Regex,
     /// Factorizations to reduce the number of similar signals, with the associated separator.
-    facts_sep: Vec<(Regex, &'static str)>,
+    facts_sep: Vec<(Regex, String)>,
 }
 
 /// Produce a pin out table.
@@ -42,102 +480,946 @@ pub fn write_pin_out(
     part_info: &db::PartInfo,
     writer: impl Write,
     filter: &SignalFilter,
+    options: &TableOptions,
 ) -> Result<()> {
-    match part_info.gpio_mode {
-        db::GpioMode::AF => write_pin_out_af(part_info, writer, filter),
-        db::GpioMode::Remap => write_pin_out_remap(part_info, writer, filter),
+    match options.format {
+        Format::Json | Format::Exclusive | Format::CHeader if options.schema_only => {
+            return Err("no schema for JSON and C header formats".into());
+        }
+        Format::Json => return write_json(part_info, writer, filter),
+        Format::Exclusive => return write_exclusive_groups(part_info, writer, filter),
+        Format::CHeader => return write_c_header(part_info, writer, filter),
+        _ => (),
+    }
+    let mut pin_out = match part_info.gpio_mode {
+        db::GpioMode::AF => pin_out_af(part_info, filter, options),
+        db::GpioMode::Remap => pin_out_remap(part_info, filter, options.remap_style),
+    };
+    if options.hide_empty {
+        pin_out
+            .rows
+            .retain(|row| row.cells.iter().any(|cell| !cell.is_empty()));
+    }
+    if options.prune_columns {
+        pin_out.prune_columns();
+    }
+    if options.schema_only {
+        pin_out.rows.clear();
+    }
+    if options.count_signals {
+        // A signal output in several AF columns is counted once.
+        pin_out.add_extra("Signals", |row| {
+            let signals = row.cells.iter().flatten().collect::<HashSet<_>>();
+            signals.len().to_string()
+        });
+    }
+    if let Some(max) = options.max_signals_per_cell {
+        if !matches!(options.format, Format::Long) {
+            pin_out.truncate_cells(max);
+        }
+    }
+    if options.show_empty_af
+        && matches!(part_info.gpio_mode, db::GpioMode::AF)
+        && matches!(
+            options.format,
+            Format::Csv | Format::Tsv | Format::Markdown | Format::Text
+        )
+    {
+        pin_out.mark_empty_af("-");
+    }
+    if options.flag_debug {
+        pin_out.add_extra("Debug", |row| row.pin.has_debug().to_string());
+    }
+    if let Some(notes) = &options.notes {
+        pin_out.add_extra("Note", |row| {
+            let pin = row.pin;
+            [&pin.name, pin.split_name().0, &pin.position]
+                .iter()
+                .find_map(|key| notes.get(*key))
+                .cloned()
+                .unwrap_or_default()
+        });
+    }
+    match options.format {
+        Format::Csv | Format::Tsv => {
+            let delimiter = if let Format::Tsv = options.format {
+                b'\t'
+            } else {
+                b','
+            };
+            let header = options.header || options.schema_only;
+            write_csv(&pin_out, writer, header, delimiter)
+        }
+        Format::Long => write_long(&pin_out, writer),
+        Format::Markdown => write_markdown(&pin_out, writer),
+        Format::Text => {
+            let header = options.header || options.schema_only;
+            write_text(&pin_out, writer, header, options.max_width)
+        }
+        Format::Influx => write_influx(&pin_out, part_info.part, writer),
+        Format::Json | Format::Exclusive | Format::CHeader => unreachable!(),
     }
 }
 
-/// Produce a pin out table for AF based parts.
-fn write_pin_out_af(
+/// Sort part pins in the given order, see `position_cmp` for package order.
+pub fn sort_pins(part_info: &mut db::PartInfo, order: PinOrder) {
+    match order {
+        PinOrder::Position => part_info
+            .pins
+            .sort_by(|a, b| position_cmp(&a.position, &b.position)),
+        PinOrder::Name => part_info.pins.sort_by(|a, b| match (a.gpio(), b.gpio()) {
+            (Some(ga), Some(gb)) => ga.cmp(&gb),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => a.name.cmp(&b.name),
+        }),
+    }
+}
+
+/// Produce a JSON document with all part information.  Signals are not shortened nor factorized,
+/// and their mapping is given explicitly.
+fn write_json(
     part_info: &db::PartInfo,
-    writer: impl Write,
+    mut writer: impl Write,
+    filter: &SignalFilter,
+) -> Result<()> {
+    let pins = part_info
+        .pins
+        .iter()
+        .filter(|pin| filter.pin_filter(pin))
+        .map(|pin| {
+            let signals = pin
+                .signals
+                .iter()
+                .map(|signal| match &signal.map {
+                    db::SignalMap::AF(af) => json!({ "name": signal.name, "type": "af", "af": af }),
+                    db::SignalMap::AddF => json!({ "name": signal.name, "type": "addf" }),
+                    db::SignalMap::Remap { remaps, groups } => json!({
+                        "name": signal.name,
+                        "type": "remap",
+                        "remaps": remaps,
+                        "groups": groups,
+                    }),
+                })
+                .collect::<Vec<_>>();
+            json!({
+                "name": pin.name,
+                "position": pin.position,
+                "type": pin.pin_type,
+                "signals": signals,
+            })
+        })
+        .collect::<Vec<_>>();
+    let gpio_mode = match part_info.gpio_mode {
+        db::GpioMode::AF => "af",
+        db::GpioMode::Remap => "remap",
+    };
+    let doc = json!({
+        "part": part_info.part,
+        "line": part_info.line,
+        "package": part_info.package,
+        "gpio_mode": gpio_mode,
+        "pins": pins,
+    });
+    serde_json::to_writer_pretty(&mut writer, &doc)?;
+    writeln!(writer)?;
+    Ok(())
+}
+
+/// Produce a JSON document with a group of mutually exclusive signals for each pin, only one
+/// signal of a group can be used at a time.  Signals are not shortened nor factorized so that the
+/// document can be fed to external assignment tools.
+fn write_exclusive_groups(
+    part_info: &db::PartInfo,
+    mut writer: impl Write,
     filter: &SignalFilter,
 ) -> Result<()> {
+    let groups = part_info
+        .pins
+        .iter()
+        .filter(|pin| filter.pin_filter(pin))
+        .map(|pin| {
+            let exclusive = pin
+                .signals
+                .iter()
+                .map(|signal| match &signal.map {
+                    db::SignalMap::AF(af) => json!({ "signal": signal.name, "af": af }),
+                    db::SignalMap::AddF => json!({ "signal": signal.name, "additional": true }),
+                    db::SignalMap::Remap { remaps, .. } => {
+                        json!({ "signal": signal.name, "remaps": remaps })
+                    }
+                })
+                .collect::<Vec<_>>();
+            json!({
+                "pin": pin.name,
+                "position": pin.position,
+                "exclusive": exclusive,
+            })
+        })
+        .collect::<Vec<_>>();
+    let doc = json!({ "part": part_info.part, "groups": groups });
+    serde_json::to_writer_pretty(&mut writer, &doc)?;
+    writeln!(writer)?;
+    Ok(())
+}
+
+/// Produce a C header with a macro for each signal of each pin, like `PINMAP_PA2_USART2_TX_AF`
+/// giving its AF number, or `PINMAP_PA9_TIM1_CH2_REMAPS` giving the list of its remaps as an
+/// array initializer.  Additional functions are not output.  Signals are not shortened nor
+/// factorized, only pins are filtered.
+fn write_c_header(
+    part_info: &db::PartInfo,
+    mut writer: impl Write,
+    filter: &SignalFilter,
+) -> Result<()> {
+    fn ident(s: &str) -> String {
+        s.chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect()
+    }
+    let guard = format!("PINMAP_{}_H", ident(part_info.part));
+    writeln!(
+        writer,
+        "/* Pin mapping for {}, generated by pinmap. */",
+        part_info.part
+    )?;
+    writeln!(writer, "#ifndef {}", guard)?;
+    writeln!(writer, "#define {}", guard)?;
+    writeln!(writer)?;
+    for pin in part_info.pins.iter().filter(|pin| filter.pin_filter(pin)) {
+        let pin_name = ident(pin.split_name().0);
+        // A signal can be mapped with several AF on the same pin, only use the first one.
+        let mut done = HashSet::new();
+        for signal in &pin.signals {
+            let (suffix, value) = match &signal.map {
+                db::SignalMap::AF(af) => ("AF", af.to_string()),
+                db::SignalMap::AddF => continue,
+                db::SignalMap::Remap { remaps, .. } => (
+                    "REMAPS",
+                    format!("{{ {} }}", remaps.iter().sorted().join(", ")),
+                ),
+            };
+            if done.insert(&signal.name) {
+                let name = format!("PINMAP_{}_{}_{}", pin_name, ident(&signal.name), suffix);
+                writeln!(writer, "#define {} {}", name, value)?;
+            }
+        }
+    }
+    writeln!(writer)?;
+    writeln!(writer, "#endif /* {} */", guard)?;
+    Ok(())
+}
+
+impl Format {
+    /// Usual file name extension for this format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Csv | Format::Long => "csv",
+            Format::Tsv => "tsv",
+            Format::Markdown => "md",
+            Format::Influx | Format::Text => "txt",
+            Format::Json | Format::Exclusive => "json",
+            Format::CHeader => "h",
+        }
+    }
+    /// Tell whether this format is meant to be read by a program, so that several tables can not
+    /// be separated by a comment line.
+    pub fn is_machine_readable(&self) -> bool {
+        matches!(
+            self,
+            Format::Influx | Format::Json | Format::Exclusive | Format::CHeader
+        )
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+    fn from_str(s: &str) -> StdResult<Format, String> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "long" => Ok(Format::Long),
+            "tsv" => Ok(Format::Tsv),
+            "markdown" => Ok(Format::Markdown),
+            "influx" => Ok(Format::Influx),
+            "json" => Ok(Format::Json),
+            "exclusive" => Ok(Format::Exclusive),
+            "text" => Ok(Format::Text),
+            "c-header" => Ok(Format::CHeader),
+            _ => Err(format!("unknown format {}", s)),
+        }
+    }
+}
+
+/// Produce an histogram of the number of signals for each AF number.
+pub fn write_af_histogram(part_info: &db::PartInfo, writer: impl Write) -> Result<()> {
+    let mut counts = Vec::new();
+    for (_, signal) in part_info.iter_signals() {
+        if let Some(af) = signal.map.af() {
+            let af = af as usize;
+            if counts.len() <= af {
+                counts.resize(af + 1, 0);
+            }
+            counts[af] += 1;
+        }
+    }
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(["AF", "count"])?;
+    for (af, count) in counts.iter().enumerate() {
+        writer.write_record([af.to_string(), count.to_string()])?;
+    }
+    Ok(())
+}
+
+/// Produce a list of product lines with their number of parts, unknown line is listed first.
+pub fn write_lines(lines: &BTreeMap<Option<String>, usize>, writer: impl Write) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(["Line", "Parts"])?;
+    for (line, count) in lines {
+        let line = line.as_deref().unwrap_or("unknown");
+        writer.write_record([line, &count.to_string()])?;
+    }
+    Ok(())
+}
+
+/// Produce a list of parts, with the database each one was found in if `with_database` is true,
+/// and the GPIO version if `with_gpio` is true.
+pub fn write_parts(
+    parts: &[(&Path, db::PartHeader)],
+    mut writer: impl Write,
+    format: PartsFormat,
+    with_database: bool,
+    with_gpio: bool,
+) -> Result<()> {
+    match format {
+        PartsFormat::Summary => {
+            for (database, header) in parts {
+                if with_database {
+                    writeln!(writer, "{} [{}]", header.summary(), database.display())?;
+                } else {
+                    writeln!(writer, "{}", header.summary())?;
+                }
+            }
+        }
+        PartsFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            let mut columns = vec!["Part", "Line", "Package"];
+            if with_gpio {
+                columns.push("GPIO");
+            }
+            if with_database {
+                columns.push("Database");
+            }
+            writer.write_record(columns)?;
+            for (database, header) in parts {
+                let mut record = vec![
+                    header.part.to_owned(),
+                    header.line.clone().unwrap_or_default(),
+                    header.package.clone().unwrap_or_default(),
+                ];
+                if with_gpio {
+                    record.push(header.gpio_version.clone().unwrap_or_default());
+                }
+                if with_database {
+                    record.push(database.display().to_string());
+                }
+                writer.write_record(record)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Produce a comparison of two parts, pins are matched by position and signals are split into
+/// the ones only available on first part, only available on second part, and common ones.
+pub fn write_diff(a: &db::PartInfo, b: &db::PartInfo, writer: impl Write) -> Result<()> {
+    let pins_a: HashMap<_, _> = a.pins.iter().map(|pin| (&pin.position, pin)).collect();
+    let pins_b: HashMap<_, _> = b.pins.iter().map(|pin| (&pin.position, pin)).collect();
+    let positions: HashSet<_> = pins_a.keys().chain(pins_b.keys()).collect();
+    let positions = positions
+        .into_iter()
+        .sorted_by(|pa, pb| position_cmp(pa, pb));
+    fn signals<'a>(pin: Option<&&'a db::PinInfo>) -> HashSet<&'a str> {
+        let signals = pin.iter().flat_map(|pin| &pin.signals);
+        signals.map(|signal| signal.name.as_str()).collect()
+    }
+    fn join<'a>(signals: impl Iterator<Item = &'a &'a str>) -> String {
+        signals.sorted_by(|a, b| natural_cmp(a, b)).join(" ")
+    }
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(["Position", "Name A", "Name B", "Only A", "Only B", "Common"])?;
+    for position in positions {
+        let (pin_a, pin_b) = (pins_a.get(position), pins_b.get(position));
+        let (signals_a, signals_b) = (signals(pin_a), signals(pin_b));
+        writer.write_record([
+            position.as_str(),
+            pin_a.map_or("", |pin| pin.name.as_str()),
+            pin_b.map_or("", |pin| pin.name.as_str()),
+            &join(signals_a.difference(&signals_b)),
+            &join(signals_b.difference(&signals_a)),
+            &join(signals_a.intersection(&signals_b)),
+        ])?;
+    }
+    Ok(())
+}
+
+/// Produce the description of a single pin, followed by each of its signals with its AF or
+/// remaps, one per line.
+pub fn write_pin(pin: &db::PinInfo, mut writer: impl Write) -> Result<()> {
+    writeln!(writer, "{} at {}, {}", pin.name, pin.position, pin.pin_type)?;
+    let width = pin.signals.iter().map(|s| s.name.len()).max().unwrap_or(0);
+    for signal in &pin.signals {
+        writeln!(
+            writer,
+            "  {:width$}  {}",
+            signal.name,
+            signal.map,
+            width = width
+        )?;
+    }
+    Ok(())
+}
+
+/// Produce the package map, with the name of the pin at each position, sorted by position.
+pub fn write_package(part_info: &db::PartInfo, writer: impl Write) -> Result<()> {
+    let mut pins = part_info.pins.iter().collect::<Vec<_>>();
+    pins.sort_by(|a, b| position_cmp(&a.position, &b.position));
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(["Position", "Name"])?;
+    for pin in pins {
+        writer.write_record([&pin.position, &pin.name])?;
+    }
+    Ok(())
+}
+
+/// Produce statistics on the number of pins and distinct signals, for the whole part, or for each
+/// GPIO port.
+pub fn write_stats(part_info: &db::PartInfo, writer: impl Write, by_port: bool) -> Result<()> {
+    fn stats<'a>(pins: impl Iterator<Item = &'a db::PinInfo>) -> [String; 2] {
+        let mut count = 0;
+        let mut signals = HashSet::new();
+        for pin in pins {
+            count += 1;
+            signals.extend(pin.signals.iter().map(|signal| signal.name.as_str()));
+        }
+        [count.to_string(), signals.len().to_string()]
+    }
     let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(["Port", "Pins", "Signals"])?;
+    if by_port {
+        for port in part_info.ports() {
+            let pins = part_info.pins.iter();
+            let pins = pins.filter(|pin| pin.gpio().map(|(p, _)| p) == Some(port));
+            let [count, signals] = stats(pins);
+            writer.write_record([&port.to_string(), &count, &signals])?;
+        }
+    } else {
+        let [count, signals] = stats(part_info.pins.iter());
+        writer.write_record(["all", &count, &signals])?;
+    }
+    Ok(())
+}
+
+/// Produce a table of all signals, giving for each of them the pins where it is available, with
+/// AF number or remaps.
+pub fn write_signals(part_info: &db::PartInfo, writer: impl Write) -> Result<()> {
+    let mut signals: HashMap<&str, Vec<String>> = HashMap::new();
     for pin in &part_info.pins {
-        let mut signals: [Vec<_>; 17] = Default::default();
+        for signal in &pin.signals {
+            let place = signal_place(pin, signal);
+            signals.entry(&signal.name).or_default().push(place);
+        }
+    }
+    let mut signals = signals.into_iter().collect::<Vec<_>>();
+    signals.sort_by(|(a, _), (b, _)| natural_cmp(a, b));
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(["Signal", "Pins"])?;
+    for (signal, places) in signals {
+        writer.write_record([signal, &places.join(" ")])?;
+    }
+    Ok(())
+}
+
+/// Produce a table with one row for each peripheral instance, followed by one cell for each of its
+/// signals giving the pins where it is available, as `SCK=PA5(AF5) PB3(AF5)`.  The number of
+/// cells varies between rows, and no header is output.  Signals are not shortened nor factorized,
+/// excludes and includes apply to full names.
+pub fn write_peripherals(
+    part_info: &db::PartInfo,
+    writer: impl Write,
+    filter: &SignalFilter,
+) -> Result<()> {
+    let mut peripherals: HashMap<&str, HashMap<&str, Vec<String>>> = HashMap::new();
+    for pin in part_info.pins.iter().filter(|pin| filter.pin_filter(pin)) {
+        for signal in &pin.signals {
+            if !filter.name_filter(&signal.name, &filter.shorten(&signal.name)) {
+                continue;
+            }
+            let (peripheral, name) = match signal.name.find('_') {
+                Some(i) => (&signal.name[..i], &signal.name[i + 1..]),
+                None => (signal.name.as_str(), ""),
+            };
+            let places = peripherals.entry(peripheral).or_default();
+            places
+                .entry(name)
+                .or_default()
+                .push(signal_place(pin, signal));
+        }
+    }
+    let mut writer = csv::WriterBuilder::new().flexible(true).from_writer(writer);
+    for (peripheral, signals) in peripherals
+        .into_iter()
+        .sorted_by(|(a, _), (b, _)| natural_cmp(a, b))
+    {
+        let mut record = vec![peripheral.to_owned()];
+        for (name, places) in signals
+            .into_iter()
+            .sorted_by(|(a, _), (b, _)| natural_cmp(a, b))
+        {
+            record.push(format!("{}={}", name, places.join(" ")));
+        }
+        writer.write_record(record)?;
+    }
+    Ok(())
+}
+
+/// Produce the list of pins carrying signals matching `signal` in every given part, with the
+/// signal mapping.
+pub fn write_signal_search<'a>(
+    parts: impl Iterator<Item = db::PartInfo<'a>>,
+    signal: &Regex,
+    writer: impl Write,
+) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(["Part", "Pin", "Position", "Signal", "Mapping"])?;
+    for part_info in parts {
+        for pin in &part_info.pins {
+            for s in pin.signals.iter().filter(|s| signal.is_match(&s.name)) {
+                writer.write_record([
+                    part_info.part,
+                    &pin.name,
+                    &pin.position,
+                    &s.name,
+                    &s.map.to_string(),
+                ])?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Give where a signal is available, with the pin name followed by AF or remaps.
+fn signal_place(pin: &db::PinInfo, signal: &db::SignalInfo) -> String {
+    match &signal.map {
+        db::SignalMap::AF(af) => format!("{}(AF{})", pin.name, af),
+        db::SignalMap::AddF => pin.name.clone(),
+        db::SignalMap::Remap { remaps, .. } => {
+            format!("{}({})", pin.name, remaps.iter().sorted().join(","))
+        }
+    }
+}
+
+/// Give remaps grouped by remap group, like `TIM2:0,2;TIM3:1`.
+fn remap_groups(map: &db::SignalMap) -> String {
+    match map {
+        db::SignalMap::Remap { remaps, groups } => {
+            let remaps = groups.iter().zip(remaps).sorted();
+            let remaps = remaps.group_by(|(group, _)| *group);
+            let mut remaps = remaps.into_iter().map(|(group, remaps)| {
+                format!("{}:{}", group, remaps.map(|(_, remap)| remap).join(","))
+            });
+            remaps.join(";")
+        }
+        _ => String::new(),
+    }
+}
+
+/// Pin out table, before rendering.
+struct PinOut<'a> {
+    /// Label of each extra pin column, output between position and signals.
+    extra_columns: Vec<&'static str>,
+    /// Label of each signal column.
+    columns: Vec<String>,
+    /// One row for each pin.
+    rows: Vec<PinOutRow<'a>>,
+}
+
+/// One pin out table row.
+struct PinOutRow<'a> {
+    /// Pin.
+    pin: &'a db::PinInfo,
+    /// Extra pin information, for each extra column.
+    extra: Vec<String>,
+    /// Signals for each column, after filtering.
+    cells: Vec<Vec<String>>,
+}
+
+impl<'a> PinOut<'a> {
+    /// Return the header row.
+    fn header(&self) -> Vec<&str> {
+        let mut header = vec!["Name", "Position"];
+        header.extend(&self.extra_columns);
+        header.extend(self.columns.iter().map(String::as_str));
+        header
+    }
+    /// Remove signal columns which are empty for every pin.
+    fn prune_columns(&mut self) {
+        let keep = (0..self.columns.len())
+            .map(|i| self.rows.iter().any(|row| !row.cells[i].is_empty()))
+            .collect::<Vec<_>>();
+        self.retain_columns(&keep);
+    }
+    /// Only keep signal columns for which `keep` is true.
+    fn retain_columns(&mut self, keep: &[bool]) {
+        let mut keep_iter = keep.iter();
+        self.columns.retain(|_| *keep_iter.next().unwrap());
+        for row in &mut self.rows {
+            let mut keep_iter = keep.iter();
+            row.cells.retain(|_| *keep_iter.next().unwrap());
+        }
+    }
+    /// Truncate cells having more than `max` signals, the number of removed signals is indicated.
+    fn truncate_cells(&mut self, max: usize) {
+        for cell in self.rows.iter_mut().flat_map(|row| &mut row.cells) {
+            if cell.len() > max {
+                let more = cell.len() - max;
+                cell.truncate(max);
+                cell.push(format!("(+{} more)", more));
+            }
+        }
+    }
+    /// Put a placeholder in empty AF cells of I/O pins, additional functions column is left
+    /// untouched.
+    fn mark_empty_af(&mut self, placeholder: &str) {
+        for row in self.rows.iter_mut().filter(|row| row.pin.pin_type == "I/O") {
+            for (column, cell) in self.columns.iter().zip(&mut row.cells) {
+                if cell.is_empty() && column.starts_with("AF") {
+                    cell.push(placeholder.to_owned());
+                }
+            }
+        }
+    }
+    /// Add an extra pin column, computing its value for each row.
+    fn add_extra(&mut self, column: &'static str, value: impl Fn(&PinOutRow) -> String) {
+        self.extra_columns.push(column);
+        for row in &mut self.rows {
+            let value = value(row);
+            row.extra.push(value);
+        }
+    }
+}
+
+/// Build a pin out table for AF based parts.  A signal available with several AF is annotated
+/// with the list of AF, and only kept in the first AF column if `collapse_dup_af` option is set.
+fn pin_out_af<'a>(
+    part_info: &'a db::PartInfo,
+    filter: &SignalFilter,
+    options: &TableOptions,
+) -> PinOut<'a> {
+    // Use at least the usual 16 AF, more if the part needs them.
+    let afs = part_info
+        .iter_signals()
+        .filter_map(|(_, signal)| signal.map.af().map(|af| af as usize + 1))
+        .fold(16, usize::max);
+    let mut columns = (0..afs).map(|af| format!("AF{}", af)).collect::<Vec<_>>();
+    // Additional functions go in their own column, after the AF ones.
+    let add = afs;
+    columns.push(String::from("ADD"));
+    // Bare GPIO signals, only present when kept by the loader, go in a last column.
+    let has_gpio = part_info
+        .iter_signals()
+        .any(|(_, signal)| signal.name == "GPIO" && matches!(signal.map, db::SignalMap::AddF));
+    let gpio = add + 1;
+    if has_gpio {
+        columns.push(String::from("GPIO"));
+    }
+    let mut rows = Vec::new();
+    for pin in part_info.pins.iter().filter(|pin| filter.pin_filter(pin)) {
+        let mut signal_afs: HashMap<&str, Vec<u8>> = HashMap::new();
+        for signal in &pin.signals {
+            if let db::SignalMap::AF(af) = signal.map {
+                signal_afs.entry(&signal.name).or_default().push(af);
+            }
+        }
+        for dup_afs in signal_afs.values_mut() {
+            dup_afs.sort_unstable();
+            dup_afs.dedup();
+        }
+        let mut signals = vec![Vec::new(); columns.len()];
         for signal in &pin.signals {
             let index = match signal.map {
                 db::SignalMap::AF(af) => af as usize,
-                db::SignalMap::AddF => signals.len() - 1,
+                db::SignalMap::AddF if signal.name == "GPIO" => gpio,
+                db::SignalMap::AddF => add,
                 _ => panic!("Bad signal map"),
             };
-            signals[index].push(signal.name.as_str());
+            let name = match signal_afs.get(signal.name.as_str()) {
+                Some(dup_afs) if dup_afs.len() > 1 => {
+                    if options.collapse_dup_af && dup_afs[0] as usize != index {
+                        continue;
+                    }
+                    let dup_afs = dup_afs.iter().map(|af| format!("AF{}", af)).join(",");
+                    format!("{}({})", signal.name, dup_afs)
+                }
+                _ => match signal.map {
+                    db::SignalMap::AF(af) if options.inline_af => {
+                        format!("{}({})", signal.name, af)
+                    }
+                    _ => signal.name.clone(),
+                },
+            };
+            signals[index].push(name);
+        }
+        let mut cells = filter.signal_filter(&pin.name, &pin.position, &signals);
+        if options.sort_cell {
+            for cell in &mut cells {
+                cell.sort_by(|a, b| natural_cmp(a, b));
+            }
         }
-        let signals = filter.signal_filter(&pin.name, &pin.position, &signals);
-        let mut row = Vec::new();
-        row.push(pin.name.clone());
-        row.push(pin.position.clone());
-        for i in &signals {
-            row.push(i.join(" "));
+        if options.mark_addf {
+            for signal in &mut cells[add] {
+                signal.insert(0, '+');
+            }
         }
-        writer.write_record(row)?;
+        rows.push(PinOutRow {
+            pin,
+            extra: Vec::new(),
+            cells,
+        });
     }
-    Ok(())
+    let columns_len = columns.len();
+    let mut pin_out = PinOut {
+        extra_columns: Vec::new(),
+        columns,
+        rows,
+    };
+    if let Some((first, last)) = options.af_range {
+        let keep = (0..columns_len)
+            .map(|i| i >= add || (first as usize..=last as usize).contains(&i))
+            .collect::<Vec<_>>();
+        pin_out.retain_columns(&keep);
+    }
+    pin_out
 }
 
-/// Produce a pin out table for Remap based parts.
-fn write_pin_out_remap(
-    part_info: &db::PartInfo,
-    writer: impl Write,
+/// Build a pin out table for Remap based parts.
+fn pin_out_remap<'a>(
+    part_info: &'a db::PartInfo,
     filter: &SignalFilter,
-) -> Result<()> {
+    style: RemapStyle,
+) -> PinOut<'a> {
     let mut lines = Vec::new();
     let mut allcats = HashSet::new();
-    for pin in &part_info.pins {
-        let signals = pin
-            .signals
-            .iter()
-            .map(|signal| {
-                if let db::SignalMap::Remap(remaps) = &signal.map {
+    for pin in part_info.pins.iter().filter(|pin| filter.pin_filter(pin)) {
+        // Group signals by remap when they are output in remap columns.
+        let mut groups: BTreeMap<Option<u8>, Vec<String>> = BTreeMap::new();
+        for signal in &pin.signals {
+            match (&signal.map, style) {
+                (db::SignalMap::Remap { remaps, .. }, RemapStyle::Inline) => {
                     let remaps = remaps.iter().sorted().map(|x| x.to_string()).join(",");
-                    format!("{}({})", signal.name, remaps)
-                } else {
-                    signal.name.clone()
+                    let name = format!("{}({})", signal.name, remaps);
+                    groups.entry(None).or_default().push(name);
                 }
-            })
-            .collect::<Vec<_>>();
-        let signals = filter
-            .signal_filter(&pin.name, &pin.position, &[signals])
-            .into_iter()
-            .next()
-            .unwrap();
+                (map @ db::SignalMap::Remap { .. }, RemapStyle::Groups) => {
+                    let name = format!("{}({})", signal.name, remap_groups(map));
+                    groups.entry(None).or_default().push(name);
+                }
+                (db::SignalMap::Remap { remaps, .. }, RemapStyle::Columns) => {
+                    for &remap in remaps {
+                        let name = signal.name.clone();
+                        groups.entry(Some(remap)).or_default().push(name);
+                    }
+                }
+                _ => groups.entry(None).or_default().push(signal.name.clone()),
+            }
+        }
+        let signals = filter.signal_filter(&pin.name, &pin.position, groups.values());
         let mut signals_hash = HashMap::new();
-        for signal in signals {
-            let cat = signal.split('_').next().unwrap().to_owned();
-            allcats.insert(cat.clone());
-            signals_hash.entry(cat).or_insert(Vec::new()).push(signal);
+        for (remap, signals) in groups.keys().zip(signals) {
+            for signal in signals {
+                let cat = signal.split('_').next().unwrap();
+                let cat = match remap {
+                    Some(remap) => format!("{}({})", cat, remap),
+                    None => cat.to_owned(),
+                };
+                allcats.insert(cat.clone());
+                signals_hash.entry(cat).or_insert(Vec::new()).push(signal);
+            }
+        }
+        for signals in signals_hash.values_mut() {
+            signals.sort_by(|a, b| natural_cmp(a, b));
         }
-        lines.push((&pin.name, &pin.position, signals_hash));
+        lines.push((pin, signals_hash));
     }
-    let mut writer = csv::Writer::from_writer(writer);
     let mut allcats = allcats.into_iter().collect::<Vec<_>>();
-    allcats.sort();
-    for (name, position, signals_hash) in lines {
-        let mut row = Vec::new();
-        row.push(name.clone());
-        row.push(position.clone());
-        for cat in &allcats {
-            let col = signals_hash.get(cat);
-            if let Some(col) = col {
-                row.push(col.iter().join(" "));
-            } else {
-                row.push(String::from(""));
+    allcats.sort_by(|a, b| natural_cmp(a, b));
+    let rows = lines
+        .into_iter()
+        .map(|(pin, mut signals_hash)| {
+            let cells = allcats
+                .iter()
+                .map(|cat| signals_hash.remove(cat).unwrap_or_default())
+                .collect();
+            PinOutRow {
+                pin,
+                extra: Vec::new(),
+                cells,
+            }
+        })
+        .collect();
+    PinOut {
+        extra_columns: Vec::new(),
+        columns: allcats,
+        rows,
+    }
+}
+
+/// Write pin out table as CSV using the given delimiter, with one row for each pin, optionally
+/// preceded by a header row.
+fn write_csv(pin_out: &PinOut, writer: impl Write, header: bool, delimiter: u8) -> Result<()> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(writer);
+    if header {
+        writer.write_record(pin_out.header())?;
+    }
+    for row in &pin_out.rows {
+        let mut record = Vec::new();
+        record.push(row.pin.name.clone());
+        record.push(row.pin.position.clone());
+        record.extend(row.extra.iter().cloned());
+        for cell in &row.cells {
+            record.push(cell.join(" "));
+        }
+        writer.write_record(record)?;
+    }
+    Ok(())
+}
+
+/// Write pin out table as a Markdown table, with one row for each pin.  The header row is always
+/// output as Markdown requires it.
+fn write_markdown(pin_out: &PinOut, mut writer: impl Write) -> Result<()> {
+    fn write_row<'s>(writer: &mut impl Write, row: impl Iterator<Item = &'s str>) -> Result<()> {
+        let row = row.map(|cell| cell.replace('|', "\\|")).join(" | ");
+        writeln!(writer, "| {} |", row)?;
+        Ok(())
+    }
+    let header = pin_out.header();
+    write_row(&mut writer, header.iter().copied())?;
+    write_row(&mut writer, header.iter().map(|_| "---"))?;
+    for row in &pin_out.rows {
+        let mut record = vec![row.pin.name.clone(), row.pin.position.clone()];
+        record.extend(row.extra.iter().cloned());
+        record.extend(row.cells.iter().map(|cell| cell.join(" ")));
+        write_row(&mut writer, record.iter().map(String::as_str))?;
+    }
+    Ok(())
+}
+
+/// Write pin out table as text with aligned columns, with one row for each pin, optionally
+/// preceded by a header row.  Cells wider than `max_width` are truncated with an ellipsis.
+fn write_text(
+    pin_out: &PinOut,
+    mut writer: impl Write,
+    header: bool,
+    max_width: Option<usize>,
+) -> Result<()> {
+    let mut records = Vec::new();
+    if header {
+        records.push(pin_out.header().iter().map(|h| h.to_string()).collect());
+    }
+    for row in &pin_out.rows {
+        let mut record = vec![row.pin.name.clone(), row.pin.position.clone()];
+        record.extend(row.extra.iter().cloned());
+        record.extend(row.cells.iter().map(|cell| cell.join(" ")));
+        records.push(record);
+    }
+    if let Some(max_width) = max_width {
+        for cell in records.iter_mut().flatten() {
+            if cell.chars().count() > max_width {
+                *cell = cell.chars().take(max_width.saturating_sub(1)).collect();
+                cell.push('…');
+            }
+        }
+    }
+    let mut widths = Vec::new();
+    for record in &records {
+        widths.resize(widths.len().max(record.len()), 0);
+        for (width, cell) in widths.iter_mut().zip(record) {
+            *width = cell.chars().count().max(*width);
+        }
+    }
+    for record in &records {
+        let line = record
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .join("  ");
+        writeln!(writer, "{}", line.trim_end())?;
+    }
+    Ok(())
+}
+
+/// Write pin out table as CSV, with one row for each pin and signal.  A pin without any signal is
+/// output on a single row with a note telling whether its signals were all removed by filtering or
+/// whether it had no signal in the first place.
+fn write_long(pin_out: &PinOut, writer: impl Write) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    let mut header = vec!["Name", "Position"];
+    header.extend(&pin_out.extra_columns);
+    header.extend(&["Column", "Signal", "Note"]);
+    writer.write_record(header)?;
+    for row in &pin_out.rows {
+        let mut pin = vec![row.pin.name.as_str(), row.pin.position.as_str()];
+        pin.extend(row.extra.iter().map(String::as_str));
+        let mut empty = true;
+        for (column, cell) in pin_out.columns.iter().zip(&row.cells) {
+            for signal in cell {
+                writer.write_record(pin.iter().chain(&[column.as_str(), signal, ""]))?;
+                empty = false;
             }
         }
-        writer.write_record(row)?;
+        if empty {
+            let note = if row.pin.signals.is_empty() {
+                "none"
+            } else {
+                "filtered"
+            };
+            writer.write_record(pin.iter().chain(&["", "", note]))?;
+        }
     }
     Ok(())
 }
 
 impl SignalFilter {
     /// Prepare a new filter.
-    pub fn new(exclude: &Vec<String>) -> StdResult<SignalFilter, regex::Error> {
-        let excludes = RegexSet::new(exclude.iter().map(|x| format!(r"^(?:{})[0-9_]", x)))?;
-        let subs = [
-            "((?:HR|LP)?T)IM",
+    pub fn new(options: &FilterOptions) -> Result<SignalFilter> {
+        let excludes = options.exclude.iter();
+        let excludes = RegexSet::new(excludes.map(|x| format!(r"^(?:{})(?:[0-9_(]|$)", x)))?;
+        let only = options.only.iter().flat_map(|preset| preset.includes());
+        let includes = options
+            .include
+            .iter()
+            .map(String::as_str)
+            .chain(only.copied());
+        let includes = RegexSet::new(includes.map(|x| format!(r"^(?:{})(?:[0-9_(]|$)", x)))?;
+        let exclude_pins = options.exclude_pin.iter();
+        let exclude_pins = RegexSet::new(exclude_pins.map(|x| format!(r"^(?:{})$", x)))?;
+        let hrtim = match &options.family {
+            Some(family) => HRTIM_FAMILIES.contains(&family.as_str()),
+            None => true,
+        };
+        let mut subs = [
+            if hrtim {
+                "((?:HR|LP)?T)IM"
+            } else {
+                "((?:LP)?T)IM"
+            },
             "((?:LP)?U)S?ART",
             "(D)FSDM",
             "(F)S?MC",
@@ -148,34 +1430,68 @@ impl SignalFilter {
             "(SD)MMC",
             "(SP)DIFRX",
             "FD(C)AN",
-            "USB_OTG_([FH]S)",
+            match options.usb_naming {
+                UsbNaming::Short => "USB_OTG_([FH]S)",
+                UsbNaming::Otg => "USB_(OTG_[FH]S)",
+                UsbNaming::Full => "",
+            },
             r"(T\d_B)KIN",
         ]
         .iter()
-        .map(|x| Regex::new(&format!(r"^{}([0-9_])", x)))
-        .collect::<StdResult<_, _>>()?;
-        let facts_sep = [
-            (r"T\d_B\d?_COMP(\d+)", ""),
-            (r"ADC(\d)_IN[NP]?\d+", ""),
-            (r"ADC\d+_IN([NP]?\d+)", ""),
-            (r"[SUT]\d_(.+)", "/"),
+        .filter(|x| !options.expand && !options.no_default_subs && !x.is_empty())
+        .map(|x| Ok((Regex::new(&format!(r"^{}([0-9_])", x))?, "$1$2".to_owned())))
+        .collect::<StdResult<Vec<_>, regex::Error>>()?;
+        for (pattern, replacement) in &options.subs {
+            let re = Regex::new(&format!(r"^(?:{})(?P<end>[0-9_])", pattern))
+                .map_err(|e| format!("bad substitution pattern {}: {}", pattern, e))?;
+            subs.push((re, format!("{}${{end}}", replacement)));
+        }
+        if options.no_shorten {
+            subs.clear();
+        }
+        // Factorizations with separator, and whether they merge several peripheral instances.
+        let mut facts_sep = [
+            (r"T\d_B\d?_COMP(\d+)", "", false),
+            (r"ADC(\d)_IN[NP]?\d+", "", true),
+            (r"ADC\d+_IN([NP]?\d+)", "", false),
+            (r"[SUT]\d_(.+)", "/", false),
         ]
         .iter()
-        .map(|(fact, sep)| Ok((Regex::new(fact)?, *sep)))
-        .collect::<StdResult<_, _>>()?;
+        .filter(|(_, _, instances)| !(options.expand && *instances))
+        .map(|(fact, sep, _)| Ok((Regex::new(fact)?, sep.to_string())))
+        .collect::<StdResult<Vec<_>, regex::Error>>()?;
+        for (pattern, sep) in &options.facts {
+            let re = Regex::new(pattern)
+                .map_err(|e| format!("bad factorization pattern {}: {}", pattern, e))?;
+            if re.captures_len() != 2 {
+                let e = "exactly one capture group is needed";
+                return Err(format!("bad factorization pattern {}: {}", pattern, e).into());
+            }
+            facts_sep.push((re, sep.clone()));
+        }
         Ok(SignalFilter {
             excludes,
+            exclude_patterns: options.exclude.clone(),
+            exclude_hits: RefCell::new(None),
+            excluded: RefCell::new(BTreeSet::new()),
+            includes,
+            io_only: options.io_only,
+            exclude_pins,
             subs,
+            sub_hits: RefCell::new(BTreeSet::new()),
+            diff_pairs: Regex::new(r"^(ADC\d+_IN)([NP])(\d+)$")?,
             facts_sep,
         })
     }
+    /// Tell whether a pin should be kept in table.
+    fn pin_filter(&self, pin: &db::PinInfo) -> bool {
+        let excluded = [&pin.name, pin.split_name().0, &pin.position]
+            .iter()
+            .any(|s| self.exclude_pins.is_match(s));
+        (!self.io_only || pin.pin_type == "I/O") && !excluded
+    }
     /// Filter a list of signal.
-    fn signal_filter<'a, I, J, S>(
-        self: &Self,
-        _name: &str,
-        _position: &str,
-        cols: I,
-    ) -> Vec<Vec<String>>
+    fn signal_filter<I, J, S>(&self, _name: &str, _position: &str, cols: I) -> Vec<Vec<String>>
     where
         S: ToString,
         J: IntoIterator<Item = S>,
@@ -185,51 +1501,310 @@ impl SignalFilter {
         for signals in cols {
             let signals = signals
                 .into_iter()
-                .map(|s| {
-                    self.subs
-                        .iter()
-                        .fold(s.to_string(), |s, re| re.replace(&s, "$1$2").to_string())
+                .filter_map(|s| {
+                    let name = s.to_string();
+                    let short = self.shorten(&name);
+                    if self.name_filter(&name, &short) {
+                        Some(short)
+                    } else {
+                        None
+                    }
                 })
                 .collect();
+            let signals = pair_differential(signals, &self.diff_pairs);
             let signals = self.facts_sep.iter().fold(signals, |signals, (fact, sep)| {
-                factorize(&signals, &fact, sep)
+                factorize(&signals, fact, sep)
             });
-            let signals = signals
-                .into_iter()
-                .filter(|s| !self.excludes.is_match(s))
-                .collect();
             res.push(signals);
         }
         res
     }
+    /// Apply all substitutions to a signal name.
+    fn shorten(&self, name: &str) -> String {
+        self.subs
+            .iter()
+            .fold(name.to_owned(), |s, (re, rep)| self.substitute(s, re, rep))
+    }
+    /// Apply a substitution to a signal name, record the shortened and original prefixes.
+    fn substitute(&self, name: String, re: &Regex, rep: &str) -> String {
+        match re.find(&name) {
+            Some(m) => {
+                let short = re.replace(&name, rep).into_owned();
+                // Both patterns and replacements end with the digit or underscore following the
+                // prefix, the rest of the name is unchanged.
+                let short_len = short.len() - (name.len() - m.end()) - 1;
+                let hit = (
+                    short[..short_len].to_owned(),
+                    name[..m.end() - 1].to_owned(),
+                );
+                self.sub_hits.borrow_mut().insert(hit);
+                short
+            }
+            None => name,
+        }
+    }
+    /// Return the shortened prefixes produced while filtering signals, with the original prefix,
+    /// sorted by shortened prefix.
+    pub fn applied_subs(&self) -> Vec<(String, String)> {
+        self.sub_hits.borrow().iter().cloned().collect()
+    }
+    /// Tell whether a signal should be kept according to includes and excludes, record which
+    /// excludes matched.  Patterns are matched against both the original and the shortened name.
+    fn name_filter(&self, name: &str, short: &str) -> bool {
+        let mut hits = self.exclude_hits.borrow_mut();
+        let hits = hits.get_or_insert_with(|| vec![false; self.excludes.len()]);
+        let mut excluded = false;
+        for i in self
+            .excludes
+            .matches(name)
+            .iter()
+            .chain(self.excludes.matches(short).iter())
+        {
+            hits[i] = true;
+            excluded = true;
+        }
+        if excluded {
+            self.excluded.borrow_mut().insert(short.to_owned());
+        }
+        let included = self.includes.is_empty()
+            || self.includes.is_match(name)
+            || self.includes.is_match(short);
+        included && !excluded
+    }
+    /// Return exclude patterns which did not match any signal, this is empty if no signal was
+    /// filtered yet.
+    pub fn unmatched_excludes(&self) -> Vec<&str> {
+        match &*self.exclude_hits.borrow() {
+            Some(hits) => self
+                .exclude_patterns
+                .iter()
+                .zip(hits)
+                .filter(|(_, hit)| !**hit)
+                .map(|(pattern, _)| pattern.as_str())
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+    /// Return signals removed by excludes, grouped by peripheral.
+    pub fn excluded_signals(&self) -> BTreeMap<String, Vec<String>> {
+        let mut signals: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for name in self.excluded.borrow().iter() {
+            let peripheral = name.split('_').next().unwrap();
+            signals
+                .entry(peripheral.to_owned())
+                .or_default()
+                .push(name.clone());
+        }
+        for names in signals.values_mut() {
+            names.sort_by(|a, b| natural_cmp(a, b));
+        }
+        signals
+    }
+}
+
+/// Write pin out table using InfluxDB line protocol.  Each pin is a point tagged with part, pin
+/// name and position, with a field containing all its signals, and a field for each non empty
+/// column.
+fn write_influx(pin_out: &PinOut, part: &str, mut writer: impl Write) -> Result<()> {
+    fn escape_tag(s: &str) -> String {
+        s.replace('\\', "\\\\")
+            .replace(',', "\\,")
+            .replace('=', "\\=")
+            .replace(' ', "\\ ")
+    }
+    fn escape_field(s: &str) -> String {
+        format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+    for row in &pin_out.rows {
+        let signals = row.cells.iter().flatten().join(" ");
+        let mut fields = vec![format!("signals={}", escape_field(&signals))];
+        let extra = pin_out.extra_columns.iter().zip(&row.extra);
+        let extra = extra.map(|(column, value)| (column.to_string(), value.clone()));
+        let cells = pin_out.columns.iter().zip(&row.cells);
+        let cells = cells
+            .filter(|(_, cell)| !cell.is_empty())
+            .map(|(column, cell)| (column.clone(), cell.join(" ")));
+        for (column, value) in extra.chain(cells) {
+            fields.push(format!("{}={}", escape_tag(&column), escape_field(&value)));
+        }
+        writeln!(
+            writer,
+            "pinmap,part={},pin={},position={} {}",
+            escape_tag(part),
+            escape_tag(&row.pin.name),
+            escape_tag(&row.pin.position),
+            fields.join(",")
+        )?;
+    }
+    Ok(())
+}
+
+/// Parsed pin position.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Position<'a> {
+    /// Pin number, used for packages with pins on their sides, like LQFP.
+    Number(u32),
+    /// Ball in a grid, used for BGA and CSP packages, with row letters and column number.
+    Grid(&'a str, u32),
+    /// Anything else, like `NC`.
+    Other(&'a str),
+}
+
+impl<'a> Position<'a> {
+    /// Parse a pin position.
+    pub fn parse(position: &'a str) -> Position<'a> {
+        let letters = position
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(position.len());
+        let (row, column) = position.split_at(letters);
+        let column = Some(column)
+            .filter(|c| c.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|c| c.parse().ok());
+        match column {
+            Some(column) if row.is_empty() => Position::Number(column),
+            Some(column) => Position::Grid(row, column),
+            None => Position::Other(position),
+        }
+    }
+}
+
+impl<'a> Ord for Position<'a> {
+    /// Numbers first, then grid positions by row, then by column, with rows ordered like `A`,
+    /// ..., `Z`, `AA`, `AB`..., then other positions.
+    fn cmp(&self, other: &Position) -> Ordering {
+        match (self, other) {
+            (Position::Number(a), Position::Number(b)) => a.cmp(b),
+            (Position::Number(_), _) => Ordering::Less,
+            (_, Position::Number(_)) => Ordering::Greater,
+            (Position::Grid(ra, ca), Position::Grid(rb, cb)) => ra
+                .len()
+                .cmp(&rb.len())
+                .then_with(|| ra.cmp(rb))
+                .then_with(|| ca.cmp(cb)),
+            (Position::Grid(..), _) => Ordering::Less,
+            (_, Position::Grid(..)) => Ordering::Greater,
+            (Position::Other(a), Position::Other(b)) => natural_cmp(a, b),
+        }
+    }
+}
+
+impl<'a> PartialOrd for Position<'a> {
+    fn partial_cmp(&self, other: &Position) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Compare pin positions, see `Position` for the order.
+pub fn position_cmp(a: &str, b: &str) -> Ordering {
+    Position::parse(a).cmp(&Position::parse(b))
+}
+
+/// Compare strings, with digits sequences compared as numbers, so that `T2` is sorted before
+/// `T10`.  When strings only differ by leading zeros, the one with less zeros comes first, so that
+/// `A2` is sorted before `A02`.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a;
+    let mut b = b;
+    let mut zeros = Ordering::Equal;
+    loop {
+        let (ca, cb) = match (a.chars().next(), b.chars().next()) {
+            (None, None) => return zeros,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(ca), Some(cb)) => (ca, cb),
+        };
+        if ca.is_ascii_digit() && cb.is_ascii_digit() {
+            let na = a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len());
+            let nb = b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len());
+            let (da, db) = (
+                a[..na].trim_start_matches('0'),
+                b[..nb].trim_start_matches('0'),
+            );
+            let ord = da.len().cmp(&db.len()).then_with(|| da.cmp(db));
+            if ord != Ordering::Equal {
+                return ord;
+            }
+            zeros = zeros.then(na.cmp(&nb));
+            a = &a[na..];
+            b = &b[nb..];
+        } else {
+            if ca != cb {
+                return ca.cmp(&cb);
+            }
+            a = &a[ca.len_utf8()..];
+            b = &b[cb.len_utf8()..];
+        }
+    }
+}
+
+/// Merge positive and negative inputs of the same differential channel, matched by the given regex
+/// with prefix, `P` or `N`, and channel groups, `ADC1_INP3` and `ADC1_INN3` give `ADC1_IN3(P/N)`.
+/// Inputs without their pair are kept as is.  A merged item is placed at its first input position.
+fn pair_differential(signals: Vec<String>, re: &Regex) -> Vec<String> {
+    let key = |s: &str| {
+        re.captures(s)
+            .map(|c| (c[1].to_owned(), c[2].to_owned(), c[3].to_owned()))
+    };
+    let keys = signals.iter().map(|s| key(s)).collect::<Vec<_>>();
+    let has_pair = |(prefix, side, channel): &(String, String, String)| {
+        let other = if side == "P" { "N" } else { "P" };
+        keys.iter()
+            .flatten()
+            .any(|(p, s, c)| p == prefix && s == other && c == channel)
+    };
+    let mut done = HashSet::new();
+    let mut res = Vec::new();
+    for (signal, key) in signals.into_iter().zip(&keys) {
+        match key {
+            Some(key) if has_pair(key) => {
+                let (prefix, _, channel) = key;
+                if done.insert((prefix, channel)) {
+                    res.push(format!("{}{}(P/N)", prefix, channel));
+                }
+            }
+            _ => res.push(signal),
+        }
+    }
+    res
 }
 
 /// For a given iterable, match each items with the given regex, if there are several matches they
-/// are factorized on the first subgroup.
+/// are factorized on the first subgroup, which must be the only one.  Items order is kept, a
+/// factorized item is placed at its first match position.
 fn factorize<I, S>(it: I, re: &Regex, sep: &str) -> Vec<String>
 where
     S: ToString,
     I: IntoIterator<Item = S>,
 {
-    let mut others = Vec::new();
-    let mut facts: HashMap<_, Vec<_>> = HashMap::new();
+    enum Item {
+        /// Factorized items, with prefix, suffix and varying terms.
+        Fact(String, String, Vec<String>),
+        /// Item not matching.
+        Other(String),
+    }
+    let mut items = Vec::new();
     for i in it {
         let i = i.to_string();
-        if let Some(c) = re.captures(&i) {
-            let g = c.get(1).expect("first group should match");
-            let termout = (&i[..g.start()], &i[g.end()..]);
-            let termout = (termout.0.to_owned(), termout.1.to_owned());
+        if let Some(g) = re.captures(&i).and_then(|c| c.get(1)) {
+            let (before, after) = (&i[..g.start()], &i[g.end()..]);
             let term = g.as_str().to_owned();
-            facts.entry(termout).or_insert(Vec::new()).push(term);
+            let fact = items.iter_mut().find_map(|item| match item {
+                Item::Fact(b, a, terms) if b == before && a == after => Some(terms),
+                _ => None,
+            });
+            match fact {
+                Some(terms) => terms.push(term),
+                None => items.push(Item::Fact(before.to_owned(), after.to_owned(), vec![term])),
+            }
         } else {
-            others.push(i);
+            items.push(Item::Other(i));
         }
     }
-    let mut r = Vec::new();
-    for (termout, terms) in facts {
-        let terms = terms.join(sep);
-        r.push(format!("{}{}{}", termout.0, terms, termout.1));
-    }
-    r.extend(others);
-    r
+    items
+        .into_iter()
+        .map(|item| match item {
+            Item::Fact(before, after, terms) => format!("{}{}{}", before, terms.join(sep), after),
+            Item::Other(i) => i,
+        })
+        .collect()
 }
@@ -0,0 +1,62 @@
+// Copyright (C) 2019 Nicolas Schodet
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Part and pin information helpers, on synthetic parts built without database.
+use pinmap::{db, PartInfo, PinInfo};
+
+/// Make a pin without signals.
+fn pin(name: &str, pin_type: &str) -> PinInfo {
+    PinInfo::new(name, "1", pin_type, Vec::new())
+}
+
+#[test]
+fn split_name() {
+    assert_eq!(pin("PA2", "I/O").split_name(), ("PA2", None));
+    let osc = pin("PC14-OSC32_IN", "I/O");
+    assert_eq!(osc.split_name(), ("PC14", Some("OSC32_IN")));
+    assert_eq!(osc.gpio(), Some(('C', 14)));
+    assert_eq!(pin("PA0-WKUP", "I/O").gpio(), Some(('A', 0)));
+    assert_eq!(pin("PB11", "I/O").gpio(), Some(('B', 11)));
+}
+
+#[test]
+fn gpio_non_gpio_names() {
+    for (name, pin_type) in [
+        ("VDD", "Power"),
+        ("BOOT0", "Boot"),
+        ("NRST", "Reset"),
+        ("PDR_ON", "Reset"),
+    ] {
+        let pin = pin(name, pin_type);
+        assert_eq!(pin.split_name(), (name, None));
+        assert_eq!(pin.gpio(), None, "{}", name);
+    }
+}
+
+#[test]
+fn ports() {
+    let pins = vec![
+        pin("VDD", "Power"),
+        pin("PD2", "I/O"),
+        pin("PA0-WKUP", "I/O"),
+        pin("PC14-OSC32_IN", "I/O"),
+        pin("PA1", "I/O"),
+        pin("BOOT0", "Boot"),
+    ];
+    let part_info = PartInfo::from_pins("STM32F401CCUx", None, None, db::GpioMode::AF, pins);
+    assert_eq!(part_info.ports(), ['A', 'C', 'D']);
+}
@@ -0,0 +1,361 @@
+// Copyright (C) 2019 Nicolas Schodet
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Parse parts from a small synthetic database, with one AF based part and one remap based part.
+use pinmap::{db, table, Database, PartInfo, SignalMap};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Synthetic database directory.
+fn database() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/db")
+}
+
+/// Copy the synthetic database to a scratch directory which can be modified by a test.
+fn scratch_database(name: &str) -> PathBuf {
+    fn copy_dir(from: &Path, to: &Path) {
+        fs::create_dir_all(to).unwrap();
+        for entry in fs::read_dir(from).unwrap() {
+            let entry = entry.unwrap();
+            let to = to.join(entry.file_name());
+            if entry.file_type().unwrap().is_dir() {
+                copy_dir(&entry.path(), &to);
+            } else {
+                fs::copy(entry.path(), to).unwrap();
+            }
+        }
+    }
+    let scratch = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = fs::remove_dir_all(&scratch);
+    copy_dir(&database(), &scratch);
+    scratch
+}
+
+/// Signals of a pin, with their mapping rendered as a string.
+fn signals(part_info: &PartInfo, pin: &str) -> Vec<(String, String)> {
+    let pin = part_info.pins.iter().find(|p| p.name == pin).unwrap();
+    pin.signals
+        .iter()
+        .map(|s| {
+            let map = match &s.map {
+                SignalMap::AF(af) => format!("AF{}", af),
+                SignalMap::AddF => String::from("ADD"),
+                SignalMap::Remap { remaps, .. } => format!("{:?}", remaps),
+            };
+            (s.name.clone(), map)
+        })
+        .collect()
+}
+
+/// Produce a table with default options.
+fn write(part_info: &PartInfo, format: table::Format) -> String {
+    let filter = table::SignalFilter::new(&table::FilterOptions {
+        family: part_info.family(),
+        ..Default::default()
+    })
+    .unwrap();
+    write_filtered(part_info, format, &filter)
+}
+
+/// Produce a table with default options and the given filter.
+fn write_filtered(
+    part_info: &PartInfo,
+    format: table::Format,
+    filter: &table::SignalFilter,
+) -> String {
+    let options = table::TableOptions {
+        format,
+        ..Default::default()
+    };
+    let mut out = Vec::new();
+    table::write_pin_out(part_info, &mut out, filter, &options).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+/// Split a signal annotated with its mapping, like `TIM1_CH2(0,1)` or `PA5(AF5)`.
+fn split_mapping(s: &str) -> (&str, Option<&str>) {
+    match s.rfind('(') {
+        Some(i) if s.ends_with(')') => (&s[..i], Some(&s[i + 1..s.len() - 1])),
+        _ => (s, None),
+    }
+}
+
+/// Flatten a long format table to a set of pin, signal and mapping.
+fn flatten_long(long: &str) -> BTreeSet<(String, String, String)> {
+    let mut reader = csv::Reader::from_reader(long.as_bytes());
+    let mut flat = BTreeSet::new();
+    for record in reader.records() {
+        let record = record.unwrap();
+        let (pin, column, signal) = (&record[0], &record[2], &record[3]);
+        if signal.is_empty() {
+            continue;
+        }
+        let (signal, mapping) = split_mapping(signal);
+        let mapping = mapping.unwrap_or(column);
+        flat.insert((pin.to_owned(), signal.to_owned(), mapping.to_owned()));
+    }
+    flat
+}
+
+/// Flatten a peripheral table to a set of pin, signal and mapping.
+fn flatten_peripherals(peripherals: &str) -> BTreeSet<(String, String, String)> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(peripherals.as_bytes());
+    let mut flat = BTreeSet::new();
+    for record in reader.records() {
+        let record = record.unwrap();
+        let peripheral = &record[0];
+        for cell in record.iter().skip(1) {
+            let (name, places) = cell.split_once('=').unwrap();
+            for place in places.split(' ') {
+                let (pin, mapping) = split_mapping(place);
+                let signal = format!("{}_{}", peripheral, name);
+                flat.insert((pin.to_owned(), signal, mapping.unwrap_or("ADD").to_owned()));
+            }
+        }
+    }
+    flat
+}
+
+/// Check that the pin out table and the peripheral table give the same signals with the given
+/// filter options, shortened names in pin out table are expanded to compare them.
+fn check_peripherals(part_info: &PartInfo, options: table::FilterOptions) {
+    let filter = table::SignalFilter::new(&options).unwrap();
+    let long = write_filtered(part_info, table::Format::Long, &filter);
+    let mut peripherals = Vec::new();
+    table::write_peripherals(part_info, &mut peripherals, &filter).unwrap();
+    let peripherals = String::from_utf8(peripherals).unwrap();
+    let subs = filter.applied_subs();
+    let flat: BTreeSet<_> = flatten_long(&long)
+        .into_iter()
+        .map(|(pin, signal, mapping)| {
+            let i = signal.find(|c: char| c == '_' || c.is_ascii_digit());
+            let (prefix, rest) = signal.split_at(i.unwrap_or(signal.len()));
+            let signal = match subs.iter().find(|(short, _)| short == prefix) {
+                Some((_, full)) => format!("{}{}", full, rest),
+                None => signal,
+            };
+            (pin, signal, mapping)
+        })
+        .collect();
+    assert!(!flat.is_empty());
+    assert_eq!(flat, flatten_peripherals(&peripherals));
+}
+
+/// Make filter options with the given excludes and includes.
+fn filter_options(exclude: &[&str], include: &[&str]) -> table::FilterOptions {
+    let strings = |patterns: &[&str]| patterns.iter().map(|x| x.to_string()).collect();
+    table::FilterOptions {
+        exclude: strings(exclude),
+        include: strings(include),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn af_part() {
+    let part_info = PartInfo::new(&database(), "STM32F405RGTx", false).unwrap();
+    assert_eq!(part_info.line.as_deref(), Some("STM32F405/415"));
+    assert_eq!(part_info.package.as_deref(), Some("LQFP64"));
+    assert!(matches!(part_info.gpio_mode, db::GpioMode::AF));
+    assert_eq!(
+        part_info.gpio_version.as_deref(),
+        Some("STM32F405_gpio_v1_0")
+    );
+    assert_eq!(part_info.family().as_deref(), Some("F4"));
+    let pins = part_info.pins.iter().map(|p| p.name.as_str());
+    assert_eq!(
+        pins.collect::<Vec<_>>(),
+        ["VBAT", "PA0-WKUP", "PA2", "PA5", "PA6"]
+    );
+    let pa0 = [
+        ("ADC1_IN0", "ADD"),
+        ("TIM2_CH1", "AF1"),
+        ("TIM5_CH1", "AF2"),
+        ("USART2_CTS", "AF7"),
+        ("SYS_WKUP", "ADD"),
+    ];
+    let pa0 = pa0.iter().map(|(s, m)| (s.to_string(), m.to_string()));
+    assert_eq!(signals(&part_info, "PA0-WKUP"), pa0.collect::<Vec<_>>());
+    assert!(part_info.validate().is_empty());
+}
+
+#[test]
+fn remap_part() {
+    let part_info = PartInfo::new(&database(), "STM32F103C8Tx", false).unwrap();
+    assert!(matches!(part_info.gpio_mode, db::GpioMode::Remap));
+    let pb6 = [
+        ("I2C1_SCL", "[0]"),
+        ("TIM4_CH1", "[0]"),
+        ("USART1_TX", "[1]"),
+    ];
+    let pb6 = pb6.iter().map(|(s, m)| (s.to_string(), m.to_string()));
+    assert_eq!(signals(&part_info, "PB6"), pb6.collect::<Vec<_>>());
+    let pa9 = signals(&part_info, "PA9");
+    assert_eq!(pa9[0], (String::from("TIM1_CH2"), String::from("[0, 1]")));
+    let pa9 = part_info.pins.iter().find(|p| p.name == "PA9").unwrap();
+    match &pa9.signals[0].map {
+        SignalMap::Remap { groups, .. } => assert_eq!(groups, &["TIM1", "TIM1"]),
+        _ => panic!("not a remap"),
+    }
+}
+
+#[test]
+fn include_gpio() {
+    let mut loader = Database::new();
+    loader.include_gpio();
+    let part_info = loader.part(&database(), "STM32F405RGTx", false).unwrap();
+    let pa2 = signals(&part_info, "PA2");
+    assert_eq!(
+        pa2.last().unwrap(),
+        &(String::from("GPIO"), String::from("ADD"))
+    );
+}
+
+#[test]
+fn missing_part() {
+    let e = PartInfo::new(&database(), "STM32F999ZZTx", false).unwrap_err();
+    assert_eq!(e.kind(), "missing-file");
+}
+
+#[test]
+fn index_unreadable_part() {
+    let databases = [scratch_database("index_unreadable_part")];
+    fs::write(databases[0].join("mcu/STM32F999ZZTx.xml"), "<Mcu").unwrap();
+    assert_eq!(db::write_index(&databases[0]).unwrap(), 3);
+    let parts = db::list_parts(&databases, ".").unwrap();
+    let parts: Vec<_> = parts.iter().map(|(_, part)| part.as_str()).collect();
+    assert_eq!(parts, ["STM32F103C8Tx", "STM32F405RGTx", "STM32F999ZZTx"]);
+}
+
+#[test]
+fn index_modified_part() {
+    let database = scratch_database("index_modified_part");
+    db::write_index(&database).unwrap();
+    // Overwrite the part in place, the directory is left unchanged.
+    fs::write(database.join("mcu/STM32F405RGTx.xml.gz"), "<Mcu").unwrap();
+    let mut loader = Database::new();
+    assert!(loader.header(&database, "STM32F405RGTx", false).is_err());
+}
+
+#[test]
+fn trimmed_database_file() {
+    let database = scratch_database("trimmed_database_file");
+    let mcu = database.join("mcu");
+    for entry in fs::read_dir(mcu.join("IP")).unwrap() {
+        let entry = entry.unwrap();
+        fs::rename(entry.path(), mcu.join(entry.file_name())).unwrap();
+    }
+    let xml_name = mcu.join("STM32F405RGTx.xml.gz");
+    let part_info =
+        PartInfo::from_file(&xml_name, &db::ip_dirs(&mcu), "STM32F405RGTx", false).unwrap();
+    assert!(matches!(part_info.gpio_mode, db::GpioMode::AF));
+    assert_eq!(
+        signals(&part_info, "PA5")[1],
+        (String::from("SPI1_SCK"), String::from("AF5"))
+    );
+}
+
+#[test]
+fn af_csv() {
+    let part_info = PartInfo::new(&database(), "STM32F405RGTx", false).unwrap();
+    let expected = "\
+Name,Position,AF0,AF1,AF2,AF3,AF4,AF5,AF6,AF7,AF8,AF9,AF10,AF11,AF12,AF13,AF14,AF15,ADD
+VBAT,1,,,,,,,,,,,,,,,,,
+PA0-WKUP,14,,T2_CH1,T5_CH1,,,,,U2_CTS,,,,,,,,,ADC1_IN0 SYS_WKUP
+PA2,16,,T2_CH3,,,,,,U2_TX,,,,,,,,,ADC1_IN2
+PA5,21,,,,T8_CH1N,,S1_SCK,,,,,,,,,,,DAC_OUT2
+PA6,22,,,,,,,,,,,,,,,,,ADC1_INP5 ADC12_IN3(P/N)
+";
+    assert_eq!(write(&part_info, table::Format::Csv), expected);
+}
+
+#[test]
+fn remap_csv() {
+    let part_info = PartInfo::new(&database(), "STM32F103C8Tx", false).unwrap();
+    let expected = "\
+Name,Position,I2C1,T1,T4,U1
+PA9,30,,\"T1_CH2(0,1)\",,U1_TX(0)
+PB6,42,I2C1_SCL(0),,T4_CH1(0),U1_TX(1)
+VDD,48,,,,
+";
+    assert_eq!(write(&part_info, table::Format::Csv), expected);
+}
+
+#[test]
+fn af_c_header() {
+    let part_info = PartInfo::new(&database(), "STM32F405RGTx", false).unwrap();
+    let header = write(&part_info, table::Format::CHeader);
+    assert!(header.contains("\n#define PINMAP_PA2_USART2_TX_AF 7\n"));
+    assert!(header.contains("\n#define PINMAP_PA0_TIM5_CH1_AF 2\n"));
+    assert!(!header.contains("ADC1_IN0"));
+}
+
+#[test]
+fn verify() {
+    let part_info = PartInfo::new(&database(), "STM32F405RGTx", false).unwrap();
+    let assignment = |pin: &str, signal: &str, mapping: Option<&str>| db::Assignment {
+        pin: pin.to_owned(),
+        signal: signal.to_owned(),
+        mapping: mapping.map(str::to_owned),
+    };
+    let assignments = [
+        assignment("PA2", "USART2_TX", Some("AF7")),
+        assignment("14", "TIM5_CH1", None),
+        assignment("PA0", "TIM5_CH1", Some("AF3")),
+        assignment("PA5", "USART2_TX", None),
+        assignment("PB0", "TIM3_CH3", None),
+    ];
+    let problems = part_info.verify(&assignments);
+    assert_eq!(problems.len(), 3);
+    assert!(problems[0].contains("AF3, but with AF2"));
+}
+
+#[test]
+fn af_peripherals() {
+    let part_info = PartInfo::new(&database(), "STM32F405RGTx", false).unwrap();
+    check_peripherals(
+        &part_info,
+        table::FilterOptions {
+            // ADC inputs are factorized in pin out table.
+            exclude: vec![String::from("ADC")],
+            expand: true,
+            no_shorten: true,
+            ..Default::default()
+        },
+    );
+    // Patterns match full and shortened names in both tables.
+    for (exclude, include) in [
+        (&["ADC"][..], &[][..]),
+        (&["ADC", "USART"], &[]),
+        (&["ADC", "U2"], &[]),
+        (&[], &["SPI"]),
+        (&[], &["S1", "TIM"]),
+    ] {
+        check_peripherals(&part_info, filter_options(exclude, include));
+    }
+}
+
+#[test]
+fn remap_peripherals() {
+    let part_info = PartInfo::new(&database(), "STM32F103C8Tx", false).unwrap();
+    check_peripherals(&part_info, Default::default());
+    check_peripherals(&part_info, filter_options(&["USART"], &[]));
+}
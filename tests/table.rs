@@ -0,0 +1,203 @@
+// Copyright (C) 2019 Nicolas Schodet
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Table helpers and pin out tables of synthetic parts, built without database.
+use pinmap::{db, table, PartInfo, PinInfo, SignalInfo, SignalMap};
+use std::cmp::Ordering;
+
+#[test]
+fn natural_cmp() {
+    let mut v = vec!["2", "10", "1"];
+    v.sort_by(|a, b| table::natural_cmp(a, b));
+    assert_eq!(v, ["1", "2", "10"]);
+    let mut v = vec!["T10", "T2", "T1_CH2", "T1"];
+    v.sort_by(|a, b| table::natural_cmp(a, b));
+    assert_eq!(v, ["T1", "T1_CH2", "T2", "T10"]);
+    assert_eq!(table::natural_cmp("A2", "A02"), Ordering::Less);
+    assert_eq!(table::natural_cmp("A02", "A2"), Ordering::Greater);
+    assert_eq!(table::natural_cmp("A02", "A3"), Ordering::Less);
+    assert_eq!(table::natural_cmp("A2", "A2"), Ordering::Equal);
+}
+
+/// Make a synthetic AF based part.
+fn af_part(pins: Vec<db::PinInfo>) -> PartInfo<'static> {
+    let line = Some(String::from("STM32F405/415"));
+    let package = Some(String::from("LQFP64"));
+    PartInfo::from_pins("STM32F405RGTx", line, package, db::GpioMode::AF, pins)
+}
+
+/// Options for a CSV table without header.
+fn csv_options() -> table::TableOptions {
+    table::TableOptions {
+        header: false,
+        prune_columns: true,
+        ..Default::default()
+    }
+}
+
+/// Produce a CSV table with default options and the given filter options.
+fn csv(part_info: &PartInfo, filter_options: &table::FilterOptions) -> String {
+    let filter = table::SignalFilter::new(filter_options).unwrap();
+    let mut out = Vec::new();
+    table::write_pin_out(part_info, &mut out, &filter, &csv_options()).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn usb_naming() {
+    let signals = vec![SignalInfo::new("USB_OTG_FS_DP", SignalMap::AF(10))];
+    let part_info = af_part(vec![PinInfo::new("PA12", "45", "I/O", signals)]);
+    let namings = [
+        (table::UsbNaming::Short, "FS_DP"),
+        (table::UsbNaming::Otg, "OTG_FS_DP"),
+        (table::UsbNaming::Full, "USB_OTG_FS_DP"),
+    ];
+    for (usb_naming, expected) in namings {
+        let options = table::FilterOptions {
+            usb_naming,
+            ..Default::default()
+        };
+        assert_eq!(csv(&part_info, &options), format!("PA12,45,{}\n", expected));
+    }
+}
+
+#[test]
+fn position_parse() {
+    use table::Position;
+    assert_eq!(Position::parse("12"), Position::Number(12));
+    assert_eq!(Position::parse("B12"), Position::Grid("B", 12));
+    assert_eq!(Position::parse("AA3"), Position::Grid("AA", 3));
+    assert_eq!(Position::parse("NC"), Position::Other("NC"));
+    assert_eq!(Position::parse("A"), Position::Other("A"));
+    assert_eq!(Position::parse(""), Position::Other(""));
+}
+
+#[test]
+fn position_order() {
+    let sorted = |positions: &[&'static str]| {
+        let mut positions = positions.to_vec();
+        positions.sort_by(|a, b| table::position_cmp(a, b));
+        positions
+    };
+    // Purely numeric.
+    assert_eq!(sorted(&["10", "2", "1"]), ["1", "2", "10"]);
+    // Grid, by row then by column, with A < Z < AA.
+    let grid = ["AA1", "A10", "Z2", "A2", "B1", "AB1"];
+    assert_eq!(sorted(&grid), ["A2", "A10", "B1", "Z2", "AA1", "AB1"]);
+    // Mixed, numbers first, then grid, then others.
+    let mixed = ["NC", "B2", "3", "A1", "12"];
+    assert_eq!(sorted(&mixed), ["3", "12", "A1", "B2", "NC"]);
+}
+
+/// Make a synthetic part with a few pins and signals.
+fn small_part() -> PartInfo<'static> {
+    let pins = vec![
+        PinInfo::new("VDD", "1", "Power", Vec::new()),
+        PinInfo::new(
+            "PA2",
+            "16",
+            "I/O",
+            vec![
+                SignalInfo::new("TIM2_CH3", SignalMap::AF(1)),
+                SignalInfo::new("USART2_TX", SignalMap::AF(7)),
+                SignalInfo::new("ADC1_IN2", SignalMap::AddF),
+            ],
+        ),
+        PinInfo::new(
+            "PA3",
+            "17",
+            "I/O",
+            vec![
+                SignalInfo::new("TIM2_CH4", SignalMap::AF(1)),
+                SignalInfo::new("TIM5_CH4", SignalMap::AF(2)),
+                SignalInfo::new("USART2_RX", SignalMap::AF(7)),
+            ],
+        ),
+    ];
+    af_part(pins)
+}
+
+#[test]
+fn synthetic_table() {
+    let part_info = small_part();
+    let expected = "\
+VDD,1,,,,
+PA2,16,T2_CH3,,U2_TX,ADC1_IN2
+PA3,17,T2_CH4,T5_CH4,U2_RX,
+";
+    assert_eq!(csv(&part_info, &Default::default()), expected);
+    let options = table::FilterOptions {
+        exclude: vec![String::from("TIM")],
+        io_only: true,
+        ..Default::default()
+    };
+    let expected = "\
+PA2,16,U2_TX,ADC1_IN2
+PA3,17,U2_RX,
+";
+    assert_eq!(csv(&part_info, &options), expected);
+    let options = table::FilterOptions {
+        include: vec![String::from("TIM")],
+        expand: true,
+        ..Default::default()
+    };
+    let expected = "\
+VDD,1,,
+PA2,16,TIM2_CH3,
+PA3,17,TIM2_CH4,TIM5_CH4
+";
+    assert_eq!(csv(&part_info, &options), expected);
+}
+
+#[test]
+fn filter_full_and_short_names() {
+    let signals = vec![
+        SignalInfo::new("SPI1_SCK", SignalMap::AF(5)),
+        SignalInfo::new("TIM2_CH1", SignalMap::AF(1)),
+    ];
+    let part_info = af_part(vec![PinInfo::new("PA5", "21", "I/O", signals)]);
+    for pattern in ["SPI", "S1"] {
+        let options = table::FilterOptions {
+            include: vec![String::from(pattern)],
+            ..Default::default()
+        };
+        assert_eq!(csv(&part_info, &options), "PA5,21,S1_SCK\n");
+    }
+    for pattern in ["TIM", "T2"] {
+        let options = table::FilterOptions {
+            exclude: vec![String::from(pattern)],
+            ..Default::default()
+        };
+        assert_eq!(csv(&part_info, &options), "PA5,21,S1_SCK\n");
+    }
+}
+
+#[test]
+fn unmatched_excludes() {
+    let filter_options = table::FilterOptions {
+        exclude: ["USART", "T5", "CAN"]
+            .iter()
+            .map(|x| x.to_string())
+            .collect(),
+        ..Default::default()
+    };
+    let filter = table::SignalFilter::new(&filter_options).unwrap();
+    assert!(filter.unmatched_excludes().is_empty());
+    let mut out = Vec::new();
+    table::write_pin_out(&small_part(), &mut out, &filter, &csv_options()).unwrap();
+    assert_eq!(filter.unmatched_excludes(), ["CAN"]);
+}